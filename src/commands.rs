@@ -0,0 +1,193 @@
+use interpreter::{Interpreter,Outcome,HISTORY_VARS};
+use types::{MalType,BUILD_IN_FUNCTION_NAMES};
+use core::{mal_typestr,mal_doc};
+use std::time::Instant;
+
+const COMMANDS : [&str;8] = [
+    ":doc", ":source", ":env", ":load", ":reload", ":time", ":type", ":quit",
+];
+
+impl Interpreter{
+    // Runs a REPL command such as ":doc map".
+    // Returns None if the code is not a command, so that keywords still evaluate.
+    // The commands print their own output, so Outcome::Value is not printed again.
+    pub fn run_command(&mut self,code:&str)->Option<Outcome>{
+        let code = code.trim();
+        let (command,arg) = match code.find(char::is_whitespace){
+            Some(i) => (&code[..i],code[i..].trim()),
+            None => (code,""),
+        };
+        if !COMMANDS.contains(&command){
+            return None;
+        }
+
+        let result = match command{
+            ":doc" => self.command_doc(arg),
+            ":source" => self.command_source(arg),
+            ":env" => self.command_env(),
+            ":load" => self.command_load(arg),
+            ":reload" => self.command_reload(),
+            ":time" => self.command_time(arg),
+            ":type" => self.command_type(arg),
+            _ => return Some(Outcome::Exit(0)), // :quit
+        };
+
+        Some(match result{
+            Ok(_) => Outcome::Value(MalType::Nil),
+            Err(e) => match self.exit_code.take(){
+                Some(code) => Outcome::Exit(code),
+                None => Outcome::Error(e),
+            },
+        })
+    }
+
+    fn lookup(&self,name:&str)->Result<MalType,String>{
+        if name.is_empty(){
+            return Err(format!("The command needs a symbol."));
+        }
+
+        match self.env.get(&name.to_string()){
+            Some(v) => Ok(v),
+            None => Err(format!("Unknown symbol: {}",name)),
+        }
+    }
+
+    fn command_doc(&mut self,name:&str)->Result<(),String>{
+        let value = match self.lookup(name){
+            Ok(v) => v,
+            Err(e) => return Err(e),
+        };
+
+        match value.clone(){
+            MalType::Function(mut args,_,is_rest,_,is_macro,_) => {
+                if is_rest{
+                    let idx = args.len()-1;
+                    args.insert(idx,"&".to_string());
+                }
+                let kind = if is_macro{ "macro" }else{ "function" };
+                println!("({}{}{})",name,if args.len() == 0{ "" }else{ " " },args.join(" "));
+                println!("  {}",kind);
+            },
+            MalType::BuiltInFunction(_) => {
+                println!("{}",name);
+                println!("  built-in function");
+            },
+            _ => {
+                println!("{}",name);
+                println!("  {}",mal_typestr(value.clone()).unwrap().to_string(false));
+            },
+        }
+        if let Ok(MalType::Str(doc)) = mal_doc(value){
+            println!("  {}",doc);
+        }
+
+        Ok(())
+    }
+
+    fn command_source(&mut self,name:&str)->Result<(),String>{
+        let value = match self.lookup(name){
+            Ok(v) => v,
+            Err(e) => return Err(e),
+        };
+
+        match value{
+            MalType::Function(_,_,_,_,_,_) => {
+                println!("{}",value.to_string(true));
+                Ok(())
+            },
+            MalType::BuiltInFunction(_) => {
+                Err(format!("{} is a built-in function and has no source.",name))
+            },
+            _ => Err(format!("{} is not a function.",name)),
+        }
+    }
+
+    // prints the definitions which are neither built-in nor from the prelude.
+    // Lazy sequences are printed without being realized, since they may be infinite.
+    fn command_env(&mut self)->Result<(),String>{
+        let mut names : Vec<String> = self.env.names()
+            .into_iter()
+            .filter(|name| !self.prelude_names.contains(name))
+            .filter(|name| !HISTORY_VARS.contains(&name.as_str()))
+            .filter(|name| !BUILD_IN_FUNCTION_NAMES.iter().any(|&(f,_,_)| f == name))
+            .collect();
+        names.sort();
+        names.dedup();
+
+        for name in names{
+            let value = self.env.get(&name).unwrap();
+            println!("{} = {}",name,value.to_string(true));
+        }
+
+        Ok(())
+    }
+
+    fn command_load(&mut self,filename:&str)->Result<(),String>{
+        if filename.is_empty(){
+            return Err(format!("The command :load needs a file name."));
+        }
+
+        match self.run_file(filename.to_string()){
+            Ok(_) => {
+                println!("Loaded {}",filename);
+                Ok(())
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    fn command_reload(&mut self)->Result<(),String>{
+        match self.loaded_file.clone(){
+            Some(filename) => self.command_load(&filename),
+            None => Err(format!("No file has been loaded.")),
+        }
+    }
+
+    fn command_time(&mut self,code:&str)->Result<(),String>{
+        let start = Instant::now();
+        let result = match self.rep(code.to_string()){
+            Ok(v) => self.realize_all(v),
+            Err(e) => Err(e),
+        };
+        let elapsed = start.elapsed();
+
+        if let Err(e) = result{
+            return Err(e);
+        }
+        self.print(result);
+        println!("Elapsed time: {:.3} msecs",elapsed.as_secs_f64()*1000.0);
+
+        Ok(())
+    }
+
+    fn command_type(&mut self,code:&str)->Result<(),String>{
+        let value = match self.rep(code.to_string()){
+            Ok(v) => v,
+            Err(e) => return Err(e),
+        };
+
+        println!("{}",mal_typestr(value).unwrap().to_string(false));
+        Ok(())
+    }
+}
+
+#[test]
+fn test_commands_0(){
+    let mut lisp = Interpreter::new();
+    lisp.load_prelude().unwrap();
+
+    assert_eq!(lisp.run_command(":foo"),None);
+    assert_eq!(lisp.run_command(":type 1"),Some(Outcome::Value(MalType::Nil)));
+    assert_eq!(lisp.run_command(":time (exit 2)"),Some(Outcome::Exit(2)));
+    assert_eq!(lisp.run_command(":quit"),Some(Outcome::Exit(0)));
+
+    lisp.rep("(def! xs (range))".to_string()).unwrap();
+    assert_eq!(lisp.run_command(":env"),Some(Outcome::Value(MalType::Nil)));
+
+    // :source prints the docstring in the fn* form
+    lisp.rep("(def! f (fn* \"adds \\\"one\\\"\" [n] (+ n 1)))".to_string()).unwrap();
+    let f = lisp.env.get(&"f".to_string()).unwrap();
+    assert_eq!(f.to_string(true),"(fn* \"adds \\\"one\\\"\" [n] (+ n 1))");
+    assert_eq!(lisp.rep("(doc (eval (read-string (pr-str f))))".to_string()),
+        Ok(MalType::Str("adds \"one\"".to_string())));
+}
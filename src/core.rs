@@ -1,4 +1,4 @@
-use types::MalType;
+use types::{MalType,BUILD_IN_FUNCTION_NAMES};
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::BufReader;
@@ -233,10 +233,12 @@ pub fn mal_typestr(x:MalType)->Result<MalType,String>{
         MalType::Identifier(_) => "symbol",
         MalType::Integer(_) => "int",
         MalType::Str(_) => "str",
+        MalType::Char(_) => "char",
+        MalType::Regex(_) => "regex",
         MalType::Bool(_) => "bool",
         MalType::Vector(_) => "vector",
         MalType::List(_) => "list",
-        MalType::Function(_,_,_,_,is_macro) => 
+        MalType::Function(_,_,_,_,is_macro,_) => 
             if is_macro {
                 "macro"
             }else{
@@ -246,6 +248,8 @@ pub fn mal_typestr(x:MalType)->Result<MalType,String>{
         MalType::Keyword(_) => "keyword",
         MalType::Dict(_) => "dict",
         MalType::Atom(_) => "atom",
+        MalType::LazySeq(_) => "lazy-seq",
+        MalType::File(_) => "file",
         MalType::Nil => "nil",
     }.to_string()))
 }
@@ -460,7 +464,7 @@ pub fn mal_dissoc(mut xs:Vec<MalType>)->Result<MalType,String>{
     };
 
     for x in xs{
-        let mut key = match x{
+        let key = match x{
             MalType::Keyword(key) => key,
             MalType::Str(s) => format!(" {}",s),
             _ => continue,
@@ -479,8 +483,7 @@ pub fn mal_seq(x:MalType)->Result<MalType,String>{
         MalType::Str(s) => {
             let mut temp = vec![];
             for c in s.chars(){
-                temp.push(
-                    MalType::Str(c.to_string()));
+                temp.push(MalType::Char(c));
             }
             temp
         },
@@ -501,4 +504,33 @@ pub fn mal_time_ms()->Result<MalType,String>{
     let in_ms = since_the_epoch.as_secs() * 1000 +
             since_the_epoch.subsec_nanos() as u64 / 1_000_000;
     Ok(MalType::Integer(in_ms as i64))
-}
\ No newline at end of file
+}
+pub fn mal_count(x:MalType)->Result<MalType,String>{
+    let n = match x{
+        MalType::List(v) => v.len(),
+        MalType::Vector(v) => v.len(),
+        MalType::Dict(hm) => hm.len(),
+        MalType::Str(s) => s.chars().count(),
+        MalType::Nil => 0,
+        _ => return Err(format!(
+            "The argument of count must be sequence, dictonary or string, we got {}.",
+            x.to_string(true))),
+    };
+
+    Ok(MalType::Integer(n as i64))
+}
+
+pub fn mal_doc(x:MalType)->Result<MalType,String>{
+    match x{
+        MalType::Function(_,_,_,_,_,Some(doc)) => Ok(MalType::Str(doc)),
+        MalType::BuiltInFunction(f) => {
+            for (_,ftype,doc) in BUILD_IN_FUNCTION_NAMES.iter(){
+                if *ftype == f{
+                    return Ok(MalType::Str(doc.to_string()));
+                }
+            }
+            Ok(MalType::Nil)
+        },
+        _ => Ok(MalType::Nil),
+    }
+}
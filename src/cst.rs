@@ -0,0 +1,237 @@
+use lexer::Lexer;
+use reader::{reader_macro,token_to_mal,dict_literal};
+use types::{TokenKind,MalType};
+
+// A node of the concrete syntax tree.
+// Unlike MalType it keeps everything in the source code, so that
+// cst_to_source(&read_cst(code)?) == code.
+#[derive(PartialEq, Debug, Clone)]
+pub enum CstNode{
+    Whitespace(String),
+    Comma,
+    Comment(String),             // ; and the rest of the line, or #! line
+    Atom(String,TokenKind),      // original spelling, token
+    Seq(String,Vec<CstNode>,String), // open bracket, children, close bracket
+    Prefix(String,Vec<CstNode>), // ', `, ~, ~@ or @, and the trivia and form after it
+}
+
+struct CstReader{
+    lexer : Lexer,
+    // the index of the source code which is not read yet
+    index : usize,
+}
+
+pub fn read_cst(code:&str)->Result<Vec<CstNode>,String>{
+    let lexer = match Lexer::with_comments(code.to_string()){
+        Ok(v) => v,
+        Err(e) => return Err(e),
+    };
+    let mut reader = CstReader{
+        lexer,
+        index : 0,
+    };
+
+    let mut nodes = match reader.read_children(None){
+        Ok(v) => v,
+        Err(e) => return Err(e),
+    };
+    // whitespace at the end of the code
+    let len = code.chars().count();
+    reader.read_trivia(len,&mut nodes);
+
+    Ok(nodes)
+}
+
+pub fn cst_to_source(nodes:&[CstNode])->String{
+    nodes.iter().map(|node| node.to_source()).collect()
+}
+
+// the forms to evaluate, without whitespace and comments
+pub fn cst_to_mal(nodes:&[CstNode])->Result<Vec<MalType>,String>{
+    let mut xs = vec![];
+
+    for node in nodes{
+        match node.to_mal(){
+            Ok(Some(x)) => xs.push(x),
+            Ok(None) => {},
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(xs)
+}
+
+impl CstNode{
+    // whitespace, commas and comments
+    pub fn is_trivia(&self)->bool{
+        matches!(self,CstNode::Whitespace(_) | CstNode::Comma | CstNode::Comment(_))
+    }
+
+    pub fn to_source(&self)->String{
+        match self{
+            CstNode::Whitespace(s) | CstNode::Comment(s) | CstNode::Atom(s,_) => s.clone(),
+            CstNode::Comma => ",".to_string(),
+            CstNode::Seq(open,children,close) =>
+                format!("{}{}{}",open,cst_to_source(children),close),
+            CstNode::Prefix(mark,children) =>
+                format!("{}{}",mark,cst_to_source(children)),
+        }
+    }
+
+    // returns None for trivia
+    pub fn to_mal(&self)->Result<Option<MalType>,String>{
+        match self{
+            CstNode::Whitespace(_) | CstNode::Comma | CstNode::Comment(_) => Ok(None),
+            CstNode::Atom(_,kind) => match token_to_mal(kind.clone()){
+                Ok(v) => Ok(Some(v)),
+                Err(e) => Err(e),
+            },
+            CstNode::Seq(open,children,_) => {
+                let xs = match cst_to_mal(children){
+                    Ok(v) => v,
+                    Err(e) => return Err(e),
+                };
+                match open.as_str(){
+                    "[" => Ok(Some(MalType::Vector(xs))),
+                    "{" => match dict_literal(xs){
+                        Ok(v) => Ok(Some(v)),
+                        Err(e) => Err(e),
+                    },
+                    _ => Ok(Some(MalType::List(xs))),
+                }
+            },
+            CstNode::Prefix(mark,children) => {
+                let func = match reader_macro(mark){
+                    Some(v) => v,
+                    None => return Err(format!("Unexpected symbol: {} ",mark)),
+                };
+                let mut xs = match cst_to_mal(children){
+                    Ok(v) => v,
+                    Err(e) => return Err(e),
+                };
+                Ok(Some(MalType::List(vec![
+                    MalType::BuiltInFunction(func),
+                    xs.remove(0),
+                ])))
+            },
+        }
+    }
+}
+
+impl CstReader{
+    // splits the source code before the index into whitespace and commas
+    fn read_trivia(&mut self,end:usize,nodes:&mut Vec<CstNode>){
+        let text = self.lexer.text(self.index,end);
+        let mut space = String::new();
+
+        for c in text.chars(){
+            if c == ','{
+                if !space.is_empty(){
+                    nodes.push(CstNode::Whitespace(space));
+                    space = String::new();
+                }
+                nodes.push(CstNode::Comma);
+            }else{
+                space.push(c);
+            }
+        }
+        if !space.is_empty(){
+            nodes.push(CstNode::Whitespace(space));
+        }
+
+        self.index = end;
+    }
+
+    fn read_children(&mut self,close:Option<&str>)->Result<Vec<CstNode>,String>{
+        let mut nodes = vec![];
+
+        loop{
+            let token = match self.lexer.peek(){
+                Some(v) => v,
+                None => return match close{
+                    Some(c) => Err(format!("Cannot found close symbol: {}",c)),
+                    None => Ok(nodes),
+                },
+            };
+            self.read_trivia(token.start,&mut nodes);
+
+            if let TokenKind::Symbol(ref s) = token.kind{
+                if Some(s.as_str()) == close{
+                    self.lexer.next();
+                    self.index = token.end;
+                    return Ok(nodes);
+                }
+            }
+
+            match self.read_node(){
+                Ok(node) => nodes.push(node),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn read_node(&mut self)->Result<CstNode,String>{
+        let token = match self.lexer.next(){
+            Some(v) => v,
+            None => return Err(format!("Unexpected end of code")),
+        };
+        self.index = token.end;
+
+        match token.kind{
+            TokenKind::Symbol(s) => {
+                let close = match s.as_str(){
+                    "(" => ")",
+                    "[" => "]",
+                    "{" => "}",
+                    ")" | "]" | "}" => return Err(format!("Unexpected symbol: {}",s)),
+                    _ => return self.read_prefix(s),
+                };
+
+                match self.read_children(Some(close)){
+                    Ok(children) => Ok(CstNode::Seq(s,children,close.to_string())),
+                    Err(e) => Err(e),
+                }
+            },
+            TokenKind::Comment(s) => Ok(CstNode::Comment(s)),
+            kind => Ok(CstNode::Atom(self.lexer.text(token.start,token.end),kind)),
+        }
+    }
+
+    // reads the trivia and the form after a reader macro
+    fn read_prefix(&mut self,mark:String)->Result<CstNode,String>{
+        let mut children = vec![];
+
+        loop{
+            let token = match self.lexer.peek(){
+                Some(v) => v,
+                None => return Err(format!("Unexpected end of code after {}",mark)),
+            };
+            self.read_trivia(token.start,&mut children);
+
+            let node = match self.read_node(){
+                Ok(v) => v,
+                Err(e) => return Err(e),
+            };
+            let is_trivia = node.is_trivia();
+            children.push(node);
+
+            if !is_trivia{
+                return Ok(CstNode::Prefix(mark,children));
+            }
+        }
+    }
+}
+
+#[test]
+fn test_cst_0(){
+    let src = "#!/usr/bin/env simple_lisp\n(def! xs [1, 0xFF ; hex\n  \"a\\tb\"])  \n' (inc ,1)\n";
+    let nodes = read_cst(src).unwrap();
+
+    assert_eq!(cst_to_source(&nodes),src);
+
+    let forms : Vec<String> = cst_to_mal(&nodes).unwrap()
+        .iter()
+        .map(|x| x.to_string(true))
+        .collect();
+    assert_eq!(forms,vec!["(def! xs [1 255 \"a\\tb\"])","(quote (inc 1))"]);
+}
@@ -1,6 +1,6 @@
 use std::collections::HashMap;
 use types::{MalType,BUILD_IN_FUNCTION_NAMES};
-use std::env;
+use loader::default_load_path;
 
 pub struct Env{
     envs : Vec<HashMap<String,MalType>>,
@@ -23,11 +23,27 @@ impl Env{
         None
     }
 
+    // the variable in the scopes of let* and functions, not in the root
+    pub fn get_local(&self,key: &String)->Option<MalType>{
+        for i in (1..self.envs.len()).rev(){
+            if let Some(v) = self.envs[i].get(key){
+                return Some(v.clone());
+            }
+        }
+
+        None
+    }
+
     pub fn set(&mut self,key: String, val:MalType){
         let n = self.envs.len()-1;
         self.envs[n].insert(key,val);
     }
 
+    // defines the variable in the outermost scope
+    pub fn set_root(&mut self,key: String, val:MalType){
+        self.envs[0].insert(key,val);
+    }
+
     pub fn let_start(&mut self){
         self.envs.push(HashMap::new());
     }
@@ -40,6 +56,24 @@ impl Env{
         }
     }
 
+    // the values of all variables in every scope
+    pub fn values(&self)->impl Iterator<Item=&MalType>{
+        self.envs.iter().flat_map(|env| env.values())
+    }
+
+    // names of all variables visible from the current scope
+    pub fn names(&self)->Vec<String>{
+        let mut names = vec![];
+
+        for env in self.envs.iter(){
+            for key in env.keys(){
+                names.push(key.clone());
+            }
+        }
+
+        names
+    }
+
     pub fn get_level(&self)->usize{
         self.envs.len()
     }
@@ -47,21 +81,32 @@ impl Env{
     fn defualt_env()->HashMap<String,MalType>{
         let mut env = HashMap::new();
 
-        for (f,ftype) in BUILD_IN_FUNCTION_NAMES.iter(){
+        for (f,ftype,_) in BUILD_IN_FUNCTION_NAMES.iter(){
             env.insert(
                 f.to_string(),
                 MalType::BuiltInFunction(ftype.clone())
             );
         }
 
-        let mut argv = vec![];
-        for argument in env::args(){
-            argv.push(MalType::Str(argument.to_string()));
-        }
-        argv.remove(0); // file path of program
+        // set by main when a script is run
         env.insert(
             "*ARGV*".to_string(),
-            MalType::List(argv)
+            MalType::List(vec![])
+        );
+        // the width pprint and the REPL lay out values in
+        env.insert(
+            "*print-width*".to_string(),
+            MalType::Integer(80)
+        );
+        // the current namespace, which ns changes
+        env.insert(
+            "*ns*".to_string(),
+            MalType::Str("user".to_string())
+        );
+        // the directories load-file and require look for files in
+        env.insert(
+            "*load-path*".to_string(),
+            default_load_path()
         );
         env.insert(
             "*host-language*".to_string(),
@@ -1,8 +1,11 @@
-use interpreter::Interpreter;
+use interpreter::{Interpreter,INTERRUPTED};
 use types::{MalType,BuiltInFunction};
 use core::*;
-use std::process::exit;
+use strings::*;
+use exception::{mal_ex_info,mal_ex_data,mal_ex_message};
+use reader::{dict_key,is_literal_key};
 use std::collections::HashMap;
+use std::sync::atomic::Ordering;
 
 impl Interpreter{
     pub fn eval(&mut self,mut ast:MalType)-> Result<MalType,String>{
@@ -10,6 +13,11 @@ impl Interpreter{
         let env_level = self.env.get_level();
         
         loop{
+            if INTERRUPTED.swap(false,Ordering::SeqCst){
+                result = Err(format!("Interrupted"));
+                break;
+            }
+
             // eprintln!("evaluating {} in eval",ast.to_string(true));
             ast = match self.mal_macroexpand(ast){
                 Err(e) => return Err(e),
@@ -22,6 +30,9 @@ impl Interpreter{
             }else if let MalType::Vector(v) = ast{
                 result = self.eval_vector(v);
                 break;
+            }else if let MalType::Dict(hm) = ast{
+                result = self.eval_dict(hm);
+                break;
             }else if ast.is_list() == false {
                 result = Ok(ast);
                 break;
@@ -89,7 +100,7 @@ impl Interpreter{
                     result = self.call_built_in_function(func_type,xs);
                     break;
                 }
-            }else if let MalType::Function(_,_,_,_,_) = f{
+            }else if let MalType::Function(_,_,_,_,_,_) = f{
                 let (argnames,body,is_rest,local_env,_) = 
                     f.unwrap_function().unwrap();
                 // eprintln!("{:?}",body.to_string(true));
@@ -118,10 +129,7 @@ impl Interpreter{
     }
 
     fn eval_identifier(&self,ident:String)-> Result<MalType,String>{
-        match self.env.get(&ident){
-            Some(v) => Ok(v.clone()),
-            None => Err(format!("Unknown symbol: {}",ident)),
-        }
+        self.resolve_identifier(&ident)
     }
 
     fn eval_sequence(&mut self,xs:Vec<MalType>)->Result<Vec<MalType>,String>{
@@ -148,6 +156,33 @@ impl Interpreter{
         }
     }
 
+    fn eval_dict(&mut self,hm:HashMap<String,MalType>)-> Result<MalType,String>{
+        let mut ys = HashMap::new();
+
+        for (key,val) in hm{
+            let key = if is_literal_key(&key){
+                key
+            }else{
+                let form = match self.dict_key_form(&key){
+                    Ok(v) => v,
+                    Err(e) => return Err(e),
+                };
+                match self.eval(form){
+                    Ok(k @ MalType::Keyword(_)) | Ok(k @ MalType::Str(_)) => dict_key(&k),
+                    Ok(v) => return Err(format!(
+                        "The key of dict must be keyword or string, we got {}.",v.to_string(true))),
+                    Err(e) => return Err(e),
+                }
+            };
+            match self.eval(val){
+                Ok(v) => ys.insert(key,v),
+                Err(e) => return Err(e),
+            };
+        }
+
+        Ok(MalType::Dict(ys))
+    }
+
     fn ready_call_function(
         &mut self,names: Vec<String>,
         body:MalType,
@@ -209,6 +244,40 @@ impl Interpreter{
         Ok(body)
     }
 
+    // Calls f with arguments which are already evaluated.
+    pub fn call_function(&mut self,f:MalType,args:Vec<MalType>)->Result<MalType,String>{
+        match f{
+            MalType::Function(_,_,_,_,_,_) => {
+                let (argnames,body,is_rest,local_env,_) = 
+                    f.unwrap_function().unwrap();
+                let env_level = self.env.get_level();
+                self.env.let_start();
+                let result = match self.ready_call_function(
+                    argnames,body,is_rest,args,local_env,false){
+                    Ok(body) => self.eval(body),
+                    Err(e) => Err(e),
+                };
+
+                while self.env.get_level() != env_level{
+                    self.env.let_end();
+                }
+
+                result
+            },
+            MalType::BuiltInFunction(_) => {
+                let mut ys : Vec<MalType> = args
+                    .into_iter()
+                    .map(|y| MalType::List(vec![
+                        MalType::BuiltInFunction(BuiltInFunction::Quote),
+                        y]))
+                    .collect();
+                ys.insert(0,f);
+                self.eval(MalType::List(ys))
+            },
+            _ => Err(format!("{} is not callable.",f.to_string(true))),
+        }
+    }
+
     fn call_built_in_function(&mut self,func_type:BuiltInFunction,mut xs: Vec<MalType>)
         -> Result<MalType,String>{
 
@@ -244,11 +313,60 @@ impl Interpreter{
                 }
             },
             BuiltInFunction::Exit =>{
-                println!("Have a nice day!");
-                exit(0)
+                match self.eval_sequence(xs){
+                    Ok(ys) => self.mal_exit(ys),
+                    Err(e) => Err(e),
+                }
+            },
+            BuiltInFunction::AtExit =>{
+                match self.eval_sequence(xs){
+                    Ok(ys) => self.mal_at_exit(ys),
+                    Err(e) => Err(e),
+                }
             },
             BuiltInFunction::Def =>{
-                self.mal_def(xs)
+                self.mal_def(xs,false)
+            },
+            BuiltInFunction::DefPrivate =>{
+                self.mal_def(xs,true)
+            },
+            BuiltInFunction::Gensym =>{
+                match self.eval_sequence(xs){
+                    Ok(ys) => self.mal_gensym(ys),
+                    Err(e) => Err(e),
+                }
+            },
+            BuiltInFunction::MacroExpand =>{
+                match self.eval_sequence(xs){
+                    Ok(ys) => self.mal_macroexpand_builtin(ys,false),
+                    Err(e) => Err(e),
+                }
+            },
+            BuiltInFunction::MacroExpand1 =>{
+                match self.eval_sequence(xs){
+                    Ok(ys) => self.mal_macroexpand_builtin(ys,true),
+                    Err(e) => Err(e),
+                }
+            },
+            BuiltInFunction::MacroExpandAll =>{
+                if xs.len() != 1{
+                    Err(format!(
+                        "The function macroexpand-all needs exactly 1 arguments, we got {}.",xs.len()))
+                }else{
+                    match self.eval(xs.pop().unwrap()){
+                        Ok(v) => self.macroexpand_all(v),
+                        Err(e) => Err(e),
+                    }
+                }
+            },
+            BuiltInFunction::Ns =>{
+                self.mal_ns(xs)
+            },
+            BuiltInFunction::Require =>{
+                match self.eval_sequence(xs){
+                    Ok(ys) => self.mal_require(ys),
+                    Err(e) => Err(e),
+                }
             },
             BuiltInFunction::Let =>{
                 Err(format!("It's a bug! `let` must be evaluated in eval"))
@@ -260,13 +378,9 @@ impl Interpreter{
                 Err(format!("It's a bug! `if` must be evaluated in eval"))
             },
             BuiltInFunction::LoadFile =>{
-                if xs.len() != 1{
-                    Err(format!(
-                        "The function quote needs exactly 1 arguments, we got {}.",xs.len()))
-                }else if let MalType::Str(filename) = xs[0].clone(){
-                    self.load_file(filename)
-                }else{
-                    Err(format!(""))
+                match self.eval_sequence(xs){
+                    Ok(ys) => self.mal_load_file(ys),
+                    Err(e) => Err(e),
                 }
             },
             BuiltInFunction::Lt =>{
@@ -288,7 +402,7 @@ impl Interpreter{
                         ,xs.len()))
                 }else{
                     match self.eval_sequence(xs){
-                        Ok(ys) => mal_eq(ys),
+                        Ok(ys) => self.lazy_eq(ys),
                         Err(e) => Err(e),
                     }
                 }
@@ -315,7 +429,11 @@ impl Interpreter{
                     Err(e) => return Err(e),
                 };
 
-                mal_nth(xs,n)
+                if let MalType::LazySeq(at) = xs{
+                    self.lazy_nth(at,n)
+                }else{
+                    mal_nth(xs,n)
+                }
             },
             BuiltInFunction::Rest => {
                 if xs.len() != 1{
@@ -324,6 +442,7 @@ impl Interpreter{
                 }
 
                 match self.eval(xs.pop().unwrap()){
+                    Ok(MalType::LazySeq(at)) => self.lazy_rest(at),
                     Ok(y) => mal_rest(y),
                     Err(e) => Err(e),
                 }
@@ -340,7 +459,11 @@ impl Interpreter{
                 }
             },
             BuiltInFunction::Insert => {
-                match self.eval_sequence(xs){
+                let ys = match self.eval_sequence(xs){
+                    Ok(v) => v,
+                    Err(e) => return Err(e),
+                };
+                match self.lazy_to_lists(ys){
                     Ok(ys) => mal_insert(ys),
                     Err(e) => Err(e),
                 }
@@ -387,6 +510,14 @@ impl Interpreter{
                 }
             }
             BuiltInFunction::PrStr => {
+                let xs = match self.eval_sequence(xs){
+                    Ok(v) => v,
+                    Err(e) => return Err(e),
+                };
+                let xs = match self.realize_sequence(xs){
+                    Ok(v) => v,
+                    Err(e) => return Err(e),
+                };
                 let ys : Vec<String> = xs
                     .into_iter()
                     .map(|x| x.to_string(true))
@@ -396,6 +527,10 @@ impl Interpreter{
             BuiltInFunction::Str => {
                 match self.eval_sequence(xs){
                     Ok(ys) => {
+                        let ys = match self.realize_sequence(ys){
+                            Ok(v) => v,
+                            Err(e) => return Err(e),
+                        };
                         let ys : Vec<String> = ys
                             .into_iter()
                             .map(|x| x.to_string(false))
@@ -516,9 +651,12 @@ impl Interpreter{
                 }
             },
             BuiltInFunction::ConCat => {
-                match self.eval_sequence(xs){
-                    Ok(ys) => 
-                        mal_concat(ys),
+                let ys = match self.eval_sequence(xs){
+                    Ok(v) => v,
+                    Err(e) => return Err(e),
+                };
+                match self.lazy_to_lists(ys){
+                    Ok(ys) => mal_concat(ys),
                     Err(e) => Err(e),
                 }
             },
@@ -538,16 +676,32 @@ impl Interpreter{
                 }
             },
             BuiltInFunction::Try => {
-                if xs.len() != 2{
-                    Err(format!(
-                        "The function throw needs exactly 2 arguments, we got {}.",xs.len()))
-                }else{
-                    self.mal_try(xs)
-                }
+                self.mal_try(xs)
             },
             BuiltInFunction::Catch => {
                 Err(format!("The function catch* must be called in try*."))
             },
+            BuiltInFunction::Finally => {
+                Err(format!("The function finally* must be called in try*."))
+            },
+            BuiltInFunction::ExInfo => {
+                match self.eval_sequence(xs){
+                    Ok(ys) => mal_ex_info(ys),
+                    Err(e) => Err(e),
+                }
+            },
+            BuiltInFunction::ExData => {
+                match self.eval_sequence(xs){
+                    Ok(ys) => mal_ex_data(ys),
+                    Err(e) => Err(e),
+                }
+            },
+            BuiltInFunction::ExMessage => {
+                match self.eval_sequence(xs){
+                    Ok(ys) => mal_ex_message(ys),
+                    Err(e) => Err(e),
+                }
+            },
             BuiltInFunction::Symbol => {
                 if xs.len() != 1{
                     Err(format!(
@@ -588,6 +742,24 @@ impl Interpreter{
                     Err(e) => Err(e),
                 }
             },
+            BuiltInFunction::Vec => {
+                if xs.len() != 1{
+                    Err(format!(
+                        "The function vec needs exactly 1 arguments, we got {}.",xs.len()))
+                }else{
+                    let x = match self.eval(xs.pop().unwrap()){
+                        Ok(v) => v,
+                        Err(e) => return Err(e),
+                    };
+                    match self.realize_all(x){
+                        Ok(MalType::List(ys)) | Ok(MalType::Vector(ys)) => Ok(MalType::Vector(ys)),
+                        Ok(MalType::Nil) => Ok(MalType::Vector(vec![])),
+                        Ok(v) => Err(format!(
+                            "The argument of vec must be sequence, we got {}.",v.to_string(true))),
+                        Err(e) => Err(e),
+                    }
+                }
+            },
             BuiltInFunction::Assoc => {
                 if xs.len() < 1{
                     Err(format!(
@@ -678,6 +850,40 @@ impl Interpreter{
                     }
                 }
             },
+            BuiltInFunction::Open => {
+                match self.eval_sequence(xs){
+                    Ok(ys) => self.mal_open(ys),
+                    Err(e) => Err(e),
+                }
+            },
+            BuiltInFunction::FileReadLine => {
+                if xs.len() != 1{
+                    Err(format!(
+                        "The function read-line needs exactly 1 arguments, we got {}.",xs.len()))
+                }else{
+                    match self.eval(xs.pop().unwrap()){
+                        Ok(MalType::File(n)) => self.file_read_line(n),
+                        Ok(v) => Err(format!(
+                            "The argument of read-line must be file, we got {}.",v.to_string(true))),
+                        Err(e) => Err(e),
+                    }
+                }
+            },
+            BuiltInFunction::Write => {
+                match self.eval_sequence(xs){
+                    Ok(ys) => self.mal_write(ys),
+                    Err(e) => Err(e),
+                }
+            },
+            BuiltInFunction::Close => {
+                match self.eval_sequence(xs){
+                    Ok(ys) => self.mal_close(ys),
+                    Err(e) => Err(e),
+                }
+            },
+            BuiltInFunction::WithOpen => {
+                self.mal_with_open(xs)
+            },
             BuiltInFunction::Seq => {
                 if xs.len() != 1{
                     Err(format!(
@@ -686,7 +892,11 @@ impl Interpreter{
                     let x = match self.eval(xs.pop().unwrap()){
                         Ok(v) => v, Err(e) => return Err(e)
                     };
-                    mal_seq(x)
+                    if let MalType::LazySeq(at) = x{
+                        self.lazy_to_seq(at)
+                    }else{
+                        mal_seq(x)
+                    }
                 }
             },
             BuiltInFunction::TimeMs => {
@@ -696,6 +906,174 @@ impl Interpreter{
                 }else{
                     mal_time_ms()
                 }
+            },
+            BuiltInFunction::LazySeq => {
+                self.mal_lazy_seq(xs)
+            },
+            BuiltInFunction::Cons => {
+                if xs.len() != 2{
+                    Err(format!(
+                        "The function cons needs exactly 2 arguments, we got {}.",xs.len()))
+                }else{
+                    let ys = match self.eval(xs.pop().unwrap()){
+                        Ok(v) => v, Err(e) => return Err(e)
+                    };
+                    let x = match self.eval(xs.pop().unwrap()){
+                        Ok(v) => v, Err(e) => return Err(e)
+                    };
+                    self.mal_cons(x,ys)
+                }
+            },
+            BuiltInFunction::Count => {
+                if xs.len() != 1{
+                    Err(format!(
+                        "The function count needs exactly 1 arguments, we got {}.",xs.len()))
+                }else{
+                    match self.eval(xs.pop().unwrap()){
+                        Ok(MalType::LazySeq(at)) => self.lazy_count(at),
+                        Ok(x) => mal_count(x),
+                        Err(e) => Err(e),
+                    }
+                }
+            },
+            BuiltInFunction::Subs => {
+                match self.eval_sequence(xs){
+                    Ok(ys) => mal_subs(ys),
+                    Err(e) => Err(e),
+                }
+            },
+            BuiltInFunction::UpperCase => {
+                match self.eval_sequence(xs){
+                    Ok(ys) => mal_upper_case(ys),
+                    Err(e) => Err(e),
+                }
+            },
+            BuiltInFunction::LowerCase => {
+                match self.eval_sequence(xs){
+                    Ok(ys) => mal_lower_case(ys),
+                    Err(e) => Err(e),
+                }
+            },
+            BuiltInFunction::Trim => {
+                match self.eval_sequence(xs){
+                    Ok(ys) => mal_trim(ys),
+                    Err(e) => Err(e),
+                }
+            },
+            BuiltInFunction::Split => {
+                match self.eval_sequence(xs){
+                    Ok(ys) => mal_split(ys),
+                    Err(e) => Err(e),
+                }
+            },
+            BuiltInFunction::Replace => {
+                match self.eval_sequence(xs){
+                    Ok(ys) => mal_replace(ys),
+                    Err(e) => Err(e),
+                }
+            },
+            BuiltInFunction::StartsWith => {
+                match self.eval_sequence(xs){
+                    Ok(ys) => mal_starts_with(ys),
+                    Err(e) => Err(e),
+                }
+            },
+            BuiltInFunction::EndsWith => {
+                match self.eval_sequence(xs){
+                    Ok(ys) => mal_ends_with(ys),
+                    Err(e) => Err(e),
+                }
+            },
+            BuiltInFunction::IndexOf => {
+                match self.eval_sequence(xs){
+                    Ok(ys) => mal_index_of(ys),
+                    Err(e) => Err(e),
+                }
+            },
+            BuiltInFunction::CharToInt => {
+                match self.eval_sequence(xs){
+                    Ok(ys) => mal_char_to_int(ys),
+                    Err(e) => Err(e),
+                }
+            },
+            BuiltInFunction::IntToChar => {
+                match self.eval_sequence(xs){
+                    Ok(ys) => mal_int_to_char(ys),
+                    Err(e) => Err(e),
+                }
+            },
+            BuiltInFunction::RePattern => {
+                match self.eval_sequence(xs){
+                    Ok(ys) => mal_re_pattern(ys),
+                    Err(e) => Err(e),
+                }
+            },
+            BuiltInFunction::ReFind => {
+                match self.eval_sequence(xs){
+                    Ok(ys) => mal_re_find(ys),
+                    Err(e) => Err(e),
+                }
+            },
+            BuiltInFunction::ReMatches => {
+                match self.eval_sequence(xs){
+                    Ok(ys) => mal_re_matches(ys),
+                    Err(e) => Err(e),
+                }
+            },
+            BuiltInFunction::ReSeq => {
+                match self.eval_sequence(xs){
+                    Ok(ys) => mal_re_seq(ys),
+                    Err(e) => Err(e),
+                }
+            },
+            BuiltInFunction::Doc => {
+                if xs.len() != 1{
+                    Err(format!(
+                        "The function doc needs exactly 1 arguments, we got {}.",xs.len()))
+                }else{
+                    match self.eval(xs.pop().unwrap()){
+                        Ok(v) => mal_doc(v),
+                        Err(e) => Err(e),
+                    }
+                }
+            },
+            BuiltInFunction::Pprint => {
+                match self.eval_sequence(xs){
+                    Ok(ys) => self.mal_pprint(ys),
+                    Err(e) => Err(e),
+                }
+            },
+            BuiltInFunction::Apropos => {
+                match self.eval_sequence(xs){
+                    Ok(ys) => self.mal_apropos(ys),
+                    Err(e) => Err(e),
+                }
+            },
+            BuiltInFunction::ReReplace => {
+                let ys = match self.eval_sequence(xs){
+                    Ok(v) => v, Err(e) => return Err(e)
+                };
+
+                match ys.get(2){
+                    Some(MalType::Function(_,_,_,_,_,_)) | Some(MalType::BuiltInFunction(_)) => {
+                        // the replacement is computed by calling the function with each match
+                        let f = ys[2].clone();
+                        regex_replace(&ys,&mut |x,_,_| match self.call_function(f.clone(),vec![x]){
+                            Ok(v) => Ok(v.to_string(false)),
+                            Err(e) => Err(e),
+                        })
+                    },
+                    _ => mal_re_replace(ys),
+                }
+            },
+            BuiltInFunction::Join => {
+                match self.eval_sequence(xs){
+                    Ok(ys) => match self.realize_sequence(ys){
+                        Ok(ys) => mal_join(ys),
+                        Err(e) => Err(e),
+                    },
+                    Err(e) => Err(e),
+                }
             }
         }
     }
@@ -722,7 +1100,7 @@ impl  Interpreter {
             };
 
             for (name,val) in var_pair{
-                if let Err(e) = self.mal_def(vec![name,val]){
+                if let Err(e) = self.mal_def(vec![name,val],false){
                     return Err(e)
                 }
             }
@@ -758,20 +1136,50 @@ impl  Interpreter {
     }
 }
 
+// removes the docstring at xs[at] from (def! name "doc" value) or (fn* "doc" [args] body)
+fn take_docstring(xs:&mut Vec<MalType>,at:usize)->Option<MalType>{
+    if xs.len() == 3 && xs[at].is_string(){
+        Some(xs.remove(at))
+    }else{
+        None
+    }
+}
+
+fn set_docstring(val:MalType,doc:Option<MalType>)->Result<MalType,String>{
+    let doc = match doc{
+        Some(MalType::Str(s)) => s,
+        _ => return Ok(val),
+    };
+
+    match val{
+        MalType::Function(varnames,body,is_rest,local_env,is_macro,_) =>
+            Ok(MalType::Function(varnames,body,is_rest,local_env,is_macro,Some(doc))),
+        _ => Err(format!(
+            "The docstring can be given only to function, we got {}.",val.to_string(true))),
+    }
+}
+
 impl Interpreter{
-    fn mal_def(&mut self,xs : Vec<MalType>)->Result<MalType,String>{
+    fn mal_def(&mut self,mut xs : Vec<MalType>,private:bool)->Result<MalType,String>{
+        // (def! f "docstring" (fn* ...))
+        let doc = take_docstring(&mut xs,1);
+        let name = if private{ "def-!" }else{ "def!" };
         if xs.len() != 2{
-            Err(format!("The function def! needs exactly 2 arguments, we got {}.",xs.len()))
+            Err(format!("The function {} needs exactly 2 arguments, we got {}.",name,xs.len()))
         }else{
             let sym = xs[0].clone();
             let val = match self.eval(xs[1].clone()){
                 Ok(v) => v,
                 Err(e) => return Err(e),
             };
+            let val = match set_docstring(val,doc){
+                Ok(v) => v,
+                Err(e) => return Err(e),
+            };
             
             match sym{
                 MalType::Identifier(ident) => {
-                    self.env.set(ident.clone(),val.clone());
+                    self.define(ident,val.clone(),private);
                     Ok(val)
                 },
 
@@ -782,6 +1190,7 @@ impl Interpreter{
     }
 
     fn mal_defmacro(&mut self,mut xs : Vec<MalType>)->Result<MalType,String>{
+        let doc = take_docstring(&mut xs,1);
         if xs.len() != 2{
             Err(format!("The function def! needs exactly 2 arguments, we got {}.",xs.len()))
         }else{
@@ -794,17 +1203,22 @@ impl Interpreter{
                 Ok(v) => v,
                 Err(e) => return Err(e),
             };
+            let val = match set_docstring(val,doc){
+                Ok(v) => v,
+                Err(e) => return Err(e),
+            };
             
             match val {
-                MalType::Function(varnames,body,is_rest,local_env,_) => {
+                MalType::Function(varnames,body,is_rest,local_env,_,doc) => {
                     let val = MalType::Function(
                         varnames,
                         body,
                         is_rest,
                         local_env,
-                        true
+                        true,
+                        doc
                     );
-                    self.env.set(sym,val.clone());
+                    self.define(sym,val.clone(),false);
                     Ok(val)
                 },
                 _ => {
@@ -830,7 +1244,7 @@ impl Interpreter{
                     };
                 }
 
-                if let MalType::Function(_,_,_,_,is_macro) = f{
+                if let MalType::Function(_,_,_,_,is_macro,_) = f{
                     is_macro
                 }else{
                     false
@@ -841,39 +1255,144 @@ impl Interpreter{
         }
     }
     fn mal_macroexpand(&mut self,mut x : MalType)->Result<MalType,String>{
-        // eprintln!("x = {}",x.to_string(true));
-        while self.is_macro_call(&x){
-            // eprintln!("we got x = {}",x.to_string(true));
-            let mut xs = x.unwrap_sequence().unwrap();
-            let mut f = xs.remove(0);
-            if let MalType::Identifier(s) = f{
-                f = match self.eval_identifier(s) {
-                    Ok(v) => v,
-                    Err(e) => return Err(e),
-                };
-            }
-
-            let (varnames,body,is_rest,local_env,_) = 
-                f.unwrap_function().unwrap();
-            self.env.let_start();
-            x = match self.ready_call_function(varnames,body,is_rest,xs,local_env,false){
-                Ok(body) => body,
-                Err(e) => return Err(e)
+        loop{
+            x = match self.macroexpand_1(x){
+                Ok((v,true)) => v,
+                Ok((v,false)) => return Ok(v),
+                Err(e) => return Err(e),
             };
+        }
+    }
+
+    // expands the macro call once, and tells whether x was a macro call
+    fn macroexpand_1(&mut self,x : MalType)->Result<(MalType,bool),String>{
+        if !self.is_macro_call(&x){
+            return Ok((x,false));
+        }
 
-            // eprintln!("we changed x = {}",x.to_string(true));
-            x = match self.eval(x){
+        let mut xs = x.unwrap_sequence().unwrap();
+        let mut f = xs.remove(0);
+        if let MalType::Identifier(s) = f{
+            f = match self.eval_identifier(s) {
                 Ok(v) => v,
                 Err(e) => return Err(e),
             };
-            // eprintln!("macroexpanded x = {}",x.to_string(true));
         }
 
-        Ok(x)
+        let (varnames,body,is_rest,local_env,_) = 
+            f.unwrap_function().unwrap();
+        let env_level = self.env.get_level();
+        self.env.let_start();
+        let result = match self.ready_call_function(varnames,body,is_rest,xs,local_env,false){
+            Ok(body) => self.eval(body),
+            Err(e) => Err(e),
+        };
+        while self.env.get_level() != env_level{
+            self.env.let_end();
+        }
+
+        match result{
+            Ok(v) => Ok((v,true)),
+            Err(e) => Err(e),
+        }
     }
 
-    fn mal_fn(&mut self,xs: Vec<MalType>)->Result<MalType,String>{
-        // (fn* [n] (+ n 1))
+    // (macroexpand-1 form) or (macroexpand form), without evaluating the expansion
+    fn mal_macroexpand_builtin(&mut self,xs:Vec<MalType>,once:bool)->Result<MalType,String>{
+        let name = if once{ "macroexpand-1" }else{ "macroexpand" };
+        if xs.len() != 1{
+            return Err(format!(
+                "The function {} needs exactly 1 arguments, we got {}.",name,xs.len()));
+        }
+
+        let x = xs.into_iter().next().unwrap();
+        if once{
+            match self.macroexpand_1(x){
+                Ok((v,_)) => Ok(v),
+                Err(e) => Err(e),
+            }
+        }else{
+            self.mal_macroexpand(x)
+        }
+    }
+
+    // expands the macro calls in the form and all its subforms, except quoted ones
+    fn macroexpand_all(&mut self,x : MalType)->Result<MalType,String>{
+        let x = match self.mal_macroexpand(x){
+            Ok(v) => v,
+            Err(e) => return Err(e),
+        };
+
+        match x{
+            MalType::List(xs) => {
+                match self.get_first_build_in_function(xs.clone()){
+                    Ok(BuiltInFunction::Quote) | Ok(BuiltInFunction::QuasiQuote) =>
+                        return Ok(MalType::List(xs)),
+                    _ => {},
+                }
+                match self.macroexpand_each(xs){
+                    Ok(ys) => Ok(MalType::List(ys)),
+                    Err(e) => Err(e),
+                }
+            },
+            MalType::Vector(xs) => match self.macroexpand_each(xs){
+                Ok(ys) => Ok(MalType::Vector(ys)),
+                Err(e) => Err(e),
+            },
+            MalType::Dict(hm) => {
+                let mut ys = HashMap::new();
+                for (key,val) in hm{
+                    match self.macroexpand_all(val){
+                        Ok(v) => ys.insert(key,v),
+                        Err(e) => return Err(e),
+                    };
+                }
+                Ok(MalType::Dict(ys))
+            },
+            _ => Ok(x),
+        }
+    }
+
+    fn macroexpand_each(&mut self,xs : Vec<MalType>)->Result<Vec<MalType>,String>{
+        let mut ys = vec![];
+
+        for x in xs{
+            match self.macroexpand_all(x){
+                Ok(y) => ys.push(y),
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(ys)
+    }
+
+    // a symbol which is not used anywhere, G__1, G__2, ...
+    pub fn gensym(&mut self,prefix:&str)->String{
+        self.gensym_counter += 1;
+        format!("{}{}",prefix,self.gensym_counter)
+    }
+
+    fn mal_gensym(&mut self,xs:Vec<MalType>)->Result<MalType,String>{
+        let prefix = match xs.len(){
+            0 => "G__".to_string(),
+            1 => match xs[0]{
+                MalType::Str(ref s) => s.clone(),
+                _ => return Err(format!(
+                    "The argument of gensym must be string, we got {}.",xs[0].to_string(true))),
+            },
+            n => return Err(format!(
+                "The function gensym needs 0 or 1 arguments, we got {}.",n)),
+        };
+
+        Ok(MalType::Identifier(self.gensym(&prefix)))
+    }
+
+    pub fn mal_fn(&mut self,mut xs: Vec<MalType>)->Result<MalType,String>{
+        // (fn* [n] (+ n 1)) or (fn* "docstring" [n] (+ n 1))
+        let doc = match take_docstring(&mut xs,0){
+            Some(MalType::Str(s)) => Some(s),
+            _ => None,
+        };
         if xs.len() != 2{
             return Err(format!(
                 "The function fn* needs exactly 2 arguments, we got {}.",xs.len()))
@@ -922,12 +1441,14 @@ impl Interpreter{
         let mut local_env = HashMap::new();
 
         for ident in idents{
-            if let Some(v) = self.env.get(&ident){
+            if let Ok(v) = self.resolve_identifier(&ident){
                 local_env.insert(ident,v);
             }
         }
+        // the body is evaluated in the namespace where the function is defined
+        local_env.insert("*ns*".to_string(),MalType::Str(self.current_ns()));
 
-        Ok(MalType::Function(names,Box::new(ast),is_rest,local_env,false))
+        Ok(MalType::Function(names,Box::new(ast),is_rest,local_env,false,doc))
     }
 
 
@@ -936,6 +1457,11 @@ impl Interpreter{
             return Err(format!("The first argument of apply must be function."))
         }
         let mut ys = match self.eval(xs.pop().unwrap()){
+            Ok(MalType::LazySeq(at)) =>
+                match self.lazy_to_list(MalType::LazySeq(at)){
+                    Ok(v) => v.unwrap_sequence().unwrap(),
+                    Err(e) => return Err(e),
+                },
             Ok(v) => 
                 if v.is_list() || v.is_vector(){
                     v.unwrap_sequence().unwrap()
@@ -964,7 +1490,7 @@ impl Interpreter{
         // eprintln!("apply result = {}",MalType::List(ys.clone()).to_string(false));
 
         match f{
-            MalType::Function(_,_,_,_,_) =>{
+            MalType::Function(_,_,_,_,_,_) =>{
                 let (argnames,body,is_rest,local_env,_) = 
                     f.unwrap_function().unwrap();
                 // eprintln!("{:?}",body.to_string(true));
@@ -978,8 +1504,7 @@ impl Interpreter{
                 self.eval(ast)
             },
             MalType::BuiltInFunction(_) =>{
-                ys.insert(0,f);
-                self.eval(MalType::List(ys))
+                self.call_function(f,ys)
             }
 
             _ => Err(format!("It's bug at apply."))
@@ -1073,105 +1598,246 @@ impl Interpreter{
     }
 
     fn mal_quasiquote(&mut self,x: MalType) -> Result<MalType,String>{
-        match self.inner_quasiquote(x){
+        // x# is replaced with the same new symbol in one quasiquote
+        let mut gensyms = HashMap::new();
+        match self.inner_quasiquote(x,0,&mut gensyms){
             Ok(v) => Ok(v.0),
             Err(e) => Err(e),
         }
     }
 
-    fn inner_quasiquote(&mut self,x: MalType) -> Result<(MalType,bool),String>{
-        if ! x.is_sequence(){
-            return Ok((x,false));
+    // depth is the number of quasiquotes inside the outermost one.
+    // ~ and ~@ are evaluated only at depth 0, and the others are kept as they are.
+    // The bool is true when the result must be spliced into the parent.
+    fn inner_quasiquote(&mut self,x: MalType,depth:usize,gensyms:&mut HashMap<String,String>)
+        -> Result<(MalType,bool),String>{
+        match x{
+            MalType::Identifier(ref s) if s.len() > 1 && s.ends_with('#') => {
+                let name = match gensyms.get(s){
+                    Some(v) => v.clone(),
+                    None => {
+                        let prefix = format!("{}__",&s[..s.len()-1]);
+                        let name = format!("{}__auto__",self.gensym(&prefix));
+                        gensyms.insert(s.clone(),name.clone());
+                        name
+                    },
+                };
+                Ok((MalType::Identifier(name),false))
+            },
+            MalType::Vector(xs) => match self.quasiquote_items(xs,depth,gensyms){
+                Ok(ys) => Ok((MalType::Vector(ys),false)),
+                Err(e) => Err(e),
+            },
+            MalType::Dict(hm) => {
+                let mut ys = HashMap::new();
+                for (key,val) in hm{
+                    // {~k 1} has the key (unquote k)
+                    let key = if is_literal_key(&key){
+                        key
+                    }else{
+                        let form = match self.dict_key_form(&key){
+                            Ok(v) => v,
+                            Err(e) => return Err(e),
+                        };
+                        match self.inner_quasiquote(form,depth,gensyms){
+                            Ok((k,_)) => dict_key(&k),
+                            Err(e) => return Err(e),
+                        }
+                    };
+                    match self.inner_quasiquote(val,depth,gensyms){
+                        Ok((v,_)) => ys.insert(key,v),
+                        Err(e) => return Err(e),
+                    };
+                }
+                Ok((MalType::Dict(ys),false))
+            },
+            MalType::List(xs) => self.quasiquote_list(xs,depth,gensyms),
+            _ => Ok((x,false)),
         }
+    }
 
-        let is_list = x.is_list();
-        let xs = x
-            .unwrap_sequence()
-            .unwrap();
-        let mut ys = vec![];
+    fn quasiquote_list(&mut self,mut xs: Vec<MalType>,depth:usize,gensyms:&mut HashMap<String,String>)
+        -> Result<(MalType,bool),String>{
+        let res_f = self.get_first_build_in_function(xs.clone());
 
-        if is_list{
-            let res_f = self.get_first_build_in_function(xs.clone());
-            
-            if res_f == Ok(BuiltInFunction::UnQuote){
-                return match self.eval(MalType::List(xs)){
-                    Ok(v) => Ok((v,false)),
-                    Err(e) => Err(e),
+        match res_f{
+            Ok(BuiltInFunction::UnQuote) | Ok(BuiltInFunction::SpliceUnQuote) if depth == 0 => {
+                let splice = res_f == Ok(BuiltInFunction::SpliceUnQuote);
+                let v = match self.eval(MalType::List(xs)){
+                    Ok(v) => v,
+                    Err(e) => return Err(e),
                 };
-            }else if res_f == Ok(BuiltInFunction::SpliceUnQuote) {
-                return match self.eval(MalType::List(xs)){
-                    Ok(v) => Ok((v,true)),
-                    Err(e) => Err(e)
+                // lazy sequences are spliced with their elements
+                if splice{
+                    match self.realize_all(v){
+                        Ok(v) => Ok((v,true)),
+                        Err(e) => Err(e),
+                    }
+                }else{
+                    Ok((v,false))
+                }
+            },
+            Ok(BuiltInFunction::UnQuote) | Ok(BuiltInFunction::SpliceUnQuote)
+            | Ok(BuiltInFunction::QuasiQuote) if xs.len() == 2 => {
+                // a nested level keeps the mark, and only the inner ~ at depth 0 is evaluated
+                let depth = if res_f == Ok(BuiltInFunction::QuasiQuote){ depth+1 }else{ depth-1 };
+                let (y,_) = match self.inner_quasiquote(xs.pop().unwrap(),depth,gensyms){
+                    Ok(v) => v,
+                    Err(e) => return Err(e),
                 };
-            }
+                xs.push(y);
+                Ok((MalType::List(xs),false))
+            },
+            _ => match self.quasiquote_items(xs,depth,gensyms){
+                Ok(ys) => Ok((MalType::List(ys),false)),
+                Err(e) => Err(e),
+            },
         }
+    }
+
+    fn quasiquote_items(&mut self,xs: Vec<MalType>,depth:usize,gensyms:&mut HashMap<String,String>)
+        -> Result<Vec<MalType>,String>{
+        let mut ys = vec![];
 
         for x in xs.into_iter(){
-            let (x,f) = match self.inner_quasiquote(x){
+            let (x,f) = match self.inner_quasiquote(x,depth,gensyms){
                 Ok(v) => v,
                 Err(e) => return Err(e),
             };
 
-            if f && x.is_sequence(){
-                ys.append(&mut x.unwrap_sequence().unwrap());
-            }else{
+            if !f{
                 ys.push(x);
+            }else if x.is_sequence(){
+                ys.append(&mut x.unwrap_sequence().unwrap());
+            }else if x != MalType::Nil{
+                // ~@nil splices nothing, and the other values are not sequences
+                return Err(format!(
+                    "The argument of splice-unquote must be sequence, we got {}.",x.to_string(true)));
             }
         }
 
-        Ok((MalType::List(ys),false))
+        Ok(ys)
     }
 
-    fn mal_try(&mut self,mut xs:Vec<MalType>)->Result<MalType,String>{
-        let err_str = match self.eval(xs.remove(0)){
-            Ok(v) => return Ok(v),
-            Err(e) => e,
+    // (exit n) unwinds the evaluation like an error which try* does not catch
+    fn mal_exit(&mut self,xs:Vec<MalType>)->Result<MalType,String>{
+        let code = match xs.len(){
+            0 => 0,
+            1 => match xs[0]{
+                MalType::Integer(n) if (0..=255).contains(&n) => n as i32,
+                _ => return Err(format!(
+                    "The argument of exit must be integer from 0 to 255, we got {}.",
+                    xs[0].to_string(true))),
+            },
+            n => return Err(format!(
+                "The function exit needs 0 or 1 arguments, we got {}.",n)),
         };
 
-        let err_val = if err_str == "Throwed an error."{
-            self.error.clone()
-        }else{
-            MalType::Str(err_str)
-        };
+        self.exit_code = Some(code);
+        Err(format!("Exit with status {}.",code))
+    }
 
-        let xs = xs.remove(0);
-        if ! xs.is_list() {
+    fn mal_at_exit(&mut self,mut xs:Vec<MalType>)->Result<MalType,String>{
+        if xs.len() != 1{
             return Err(format!(
-                "The second argument of try* must be catch* function call."))
+                "The function at-exit needs exactly 1 arguments, we got {}.",xs.len()));
+        }
+
+        let f = xs.remove(0);
+        match f{
+            MalType::Function(_,_,_,_,_,_) | MalType::BuiltInFunction(_) => {
+                self.exit_hooks.push(f);
+                Ok(MalType::Nil)
+            },
+            _ => Err(format!(
+                "The argument of at-exit must be function, we got {}.",f.to_string(true))),
         }
+    }
 
-        let mut xs = xs.unwrap_sequence().unwrap();
-        if xs.len() != 3{
+    fn mal_apropos(&mut self,xs:Vec<MalType>)->Result<MalType,String>{
+        if xs.len() != 1{
             return Err(format!(
-                "The second argument of try* is (catch* err-var result). We got {}.",
-                MalType::List(xs).to_string(false)));
+                "The function apropos needs exactly 1 arguments, we got {}.",xs.len()));
         }
 
-        let f = match self.eval(xs.remove(0)){
-            Ok(v) => v,
-            Err(e) => return Err(e),
+        let mut names : Vec<String> = match xs[0]{
+            MalType::Str(ref s) => self.env.names()
+                .into_iter()
+                .filter(|name| name.contains(s.as_str()))
+                .collect(),
+            MalType::Regex(ref re) => {
+                let mut names = vec![];
+                for name in self.env.names(){
+                    let chars : Vec<char> = name.chars().collect();
+                    match re.find_at(&chars,0){
+                        Ok(Some(_)) => names.push(name),
+                        Ok(None) => {},
+                        Err(e) => return Err(e),
+                    }
+                }
+                names
+            },
+            _ => return Err(format!(
+                "The argument of apropos must be string or regex, we got {}.",
+                xs[0].to_string(true))),
         };
-        let varname = xs.remove(0);
-        let result = xs.remove(0);
+        names.sort();
+        names.dedup();
 
-        if f != MalType::BuiltInFunction(BuiltInFunction::Catch){
-            return Err(format!(
-                "The second argument of try* is (catch* err-var result). "));
-        }
+        Ok(MalType::List(names.into_iter().map(MalType::Identifier).collect()))
+    }
 
-        if varname.unwrap_identifier().is_none() {
-            return Err(format!(
-                "The first argument of catch* must be identifier, we got {}.",
-                varname.to_string(false)));
+    // the value catch* binds for the error
+    pub fn error_value(&self,err_str:String)->MalType{
+        if err_str == "Throwed an error."{
+            self.error.clone()
+        }else{
+            MalType::Str(err_str)
         }
-        
-        self.env.let_start();
-        self.env.set(varname.unwrap_identifier().unwrap(), err_val);
-
-        let result = self.eval(result);
+    }
+}
 
-        self.env.let_end();
+#[test]
+fn test_macro_hygiene_0(){
+    let mut lisp = Interpreter::new();
+    lisp.load_prelude().unwrap();
+
+    // or binds x# internally, which must not capture the x of the caller
+    assert_eq!(lisp.rep("(let* [x 2] (or false x))".to_string()),Ok(MalType::Integer(2)));
+    assert_eq!(lisp.rep("(let* [x 3] (or nil (or false x)))".to_string()),Ok(MalType::Integer(3)));
+
+    // x# is the same symbol within one syntax-quote and a new one in another
+    let code = "(let* [form `(x# x# y#)]
+                  [(= (nth form 0) (nth form 1)) (= (nth form 0) (nth form 2))
+                   (= (first `(x#)) (nth form 0)) (= 'x (nth form 0))])";
+    assert_eq!(lisp.rep(code.to_string()).unwrap().to_string(true),"[true false false false]");
+    let code = "(let* [e (macroexpand-1 '(or a b)) v (first (nth e 1))]
+                  [(first e) (= v (first (rest (nth e 2))))])";
+    assert_eq!(lisp.rep(code.to_string()).unwrap().to_string(true),"[let* true]");
+    assert_eq!(
+        lisp.rep("(macroexpand-all '(fn* [x] [(cond x 1) '(or a)]))".to_string()).unwrap().to_string(true),
+        "(fn* [x] [(if x 1 nil) (quote (or a))])");
+    assert_eq!(
+        lisp.rep("(macroexpand '(cond a 1))".to_string()).unwrap().to_string(true),
+        "(if a 1 (cond))");
+}
 
-        result
+#[test]
+fn test_quasiquote_0(){
+    let mut lisp = Interpreter::new();
+    lisp.rep("(def! x 7) (def! xs [1 2])".to_string()).unwrap();
+
+    let cases = [
+        ("`[0 ~x ~@xs]","[0 7 1 2]"),
+        ("`{:a ~x}","{:a 7}"),
+        ("`(1 `(2 ~(3 ~x)))","(1 (quasiquote (2 (unquote (3 7)))))"),
+        ("(vec '(1 2))","[1 2]"),
+        ("`(1 ~@nil)","(1)"),
+    ];
+    for (code,expected) in cases.iter(){
+        assert_eq!(lisp.rep(code.to_string()).unwrap().to_string(true),*expected);
     }
+
+    assert_eq!(lisp.rep("`(1 ~@5)".to_string()),
+        Err("Runtime error: The argument of splice-unquote must be sequence, we got 5.".to_string()));
 }
@@ -0,0 +1,260 @@
+use interpreter::Interpreter;
+use types::{MalType,BuiltInFunction};
+use std::collections::HashMap;
+
+// the keys of the dict ex-info makes
+const MESSAGE_KEY : &str = ":ex-message";
+const DATA_KEY : &str = ":ex-data";
+
+// (catch* selector? e handler): the selector (None catches everything), variable and handler
+type CatchClause = (Option<MalType>,String,MalType);
+
+fn is_truthy(x:&MalType)->bool{
+    !matches!(x,MalType::Nil | MalType::Bool(false))
+}
+
+fn is_ex_info(x:&MalType)->bool{
+    match x{
+        MalType::Dict(hm) => hm.contains_key(MESSAGE_KEY),
+        _ => false,
+    }
+}
+
+impl Interpreter{
+    // (try* expr (catch* e handler) (catch* :ex-info e handler) (finally* cleanup ...))
+    pub fn mal_try(&mut self,mut xs:Vec<MalType>)->Result<MalType,String>{
+        if xs.len() == 0{
+            return Err(format!("The function try* needs at least 1 arguments, we got 0."));
+        }
+        let expr = xs.remove(0);
+        let (catches,finally) = match self.parse_clauses(xs){
+            Ok(v) => v,
+            Err(e) => return Err(e),
+        };
+
+        let mut result = self.eval(expr);
+
+        // (exit n) and Ctrl-C are not caught, but finally* runs
+        if let Err(err_str) = result.clone(){
+            if self.exit_code.is_none() && err_str != "Interrupted"{
+                result = self.run_catch(&catches,err_str);
+            }
+        }
+
+        // an error caught in finally* must not replace the pending one
+        let pending = self.error.clone();
+        for x in finally{
+            if let Err(e) = self.eval(x){
+                return Err(e);
+            }
+        }
+        self.error = pending;
+
+        result
+    }
+
+    // the catch* clauses and the body of finally*
+    fn parse_clauses(&mut self,xs:Vec<MalType>)->Result<(Vec<CatchClause>,Vec<MalType>),String>{
+        let mut catches = vec![];
+        let mut finally = vec![];
+        let n = xs.len();
+
+        for (i,x) in xs.into_iter().enumerate(){
+            let mut ys = match x{
+                MalType::List(ys) if ys.len() > 0 => ys,
+                _ => return Err(format!(
+                    "The clause of try* must be (catch* ...) or (finally* ...), we got {}.",
+                    x.to_string(true))),
+            };
+
+            let f = match self.eval(ys.remove(0)){
+                Ok(v) => v,
+                Err(e) => return Err(e),
+            };
+            match f{
+                MalType::BuiltInFunction(BuiltInFunction::Catch) => {
+                    // (catch* e handler) or (catch* selector e handler)
+                    let selector = match ys.len(){
+                        2 => None,
+                        3 => Some(ys.remove(0)),
+                        _ => return Err(format!(
+                            "The clause catch* is (catch* selector? err-var handler). We got {} arguments.",
+                            ys.len())),
+                    };
+                    let varname = match ys.remove(0){
+                        MalType::Identifier(s) => s,
+                        v => return Err(format!(
+                            "The err-var of catch* must be identifier, we got {}.",v.to_string(false))),
+                    };
+                    catches.push((selector,varname,ys.remove(0)));
+                },
+                MalType::BuiltInFunction(BuiltInFunction::Finally) => {
+                    if i != n-1{
+                        return Err(format!("The clause finally* must be the last one of try*."));
+                    }
+                    finally = ys;
+                },
+                _ => return Err(format!(
+                    "The clause of try* must be (catch* ...) or (finally* ...).")),
+            }
+        }
+
+        Ok((catches,finally))
+    }
+
+    // evaluates the handler of the first catch* which selects the error
+    fn run_catch(&mut self,catches:&[CatchClause],err_str:String)->Result<MalType,String>{
+        let thrown = err_str == "Throwed an error.";
+        let err_val = self.error_value(err_str.clone());
+
+        for (selector,varname,handler) in catches{
+            let selected = match selector{
+                None => Ok(true),
+                Some(s) => self.is_selected(s.clone(),&err_val,thrown),
+            };
+            match selected{
+                Ok(true) => {},
+                Ok(false) => continue,
+                Err(e) => return Err(e),
+            }
+
+            self.env.let_start();
+            self.env.set(varname.clone(),err_val);
+            let result = self.eval(handler.clone());
+            self.env.let_end();
+
+            return result;
+        }
+
+        // the selectors may have thrown and caught other values
+        self.error = err_val;
+        Err(err_str)
+    }
+
+    // The selector is a keyword of the kind of the error or a predicate.
+    // :error is an error of the interpreter, :thrown is a value of throw,
+    // :ex-info is a value of ex-info thrown, and :default is anything.
+    fn is_selected(&mut self,selector:MalType,err_val:&MalType,thrown:bool)->Result<bool,String>{
+        if let MalType::Keyword(ref k) = selector{
+            return match k.as_str(){
+                ":default" => Ok(true),
+                ":error" => Ok(!thrown),
+                ":thrown" => Ok(thrown),
+                ":ex-info" => Ok(thrown && is_ex_info(err_val)),
+                _ => Err(format!("Unknown kind of error in catch*: {}",k)),
+            };
+        }
+
+        let pred = match self.eval(selector){
+            Ok(v) => v,
+            Err(e) => return Err(e),
+        };
+        match pred{
+            MalType::Function(_,_,_,_,_,_) | MalType::BuiltInFunction(_) =>
+                match self.call_function(pred,vec![err_val.clone()]){
+                    Ok(v) => Ok(is_truthy(&v)),
+                    Err(e) => Err(e),
+                },
+            _ => Err(format!(
+                "The selector of catch* must be keyword or function, we got {}.",
+                pred.to_string(true))),
+        }
+    }
+}
+
+// (ex-info "message" {:key value})
+pub fn mal_ex_info(xs:Vec<MalType>)->Result<MalType,String>{
+    if xs.len() != 1 && xs.len() != 2{
+        return Err(format!(
+            "The function ex-info needs 1 or 2 arguments, we got {}.",xs.len()));
+    }
+    let message = match xs[0]{
+        MalType::Str(_) => xs[0].clone(),
+        _ => return Err(format!(
+            "The first argument of ex-info must be string, we got {}.",xs[0].to_string(true))),
+    };
+
+    let mut hm = HashMap::new();
+    hm.insert(MESSAGE_KEY.to_string(),message);
+    hm.insert(DATA_KEY.to_string(),xs.get(1).cloned().unwrap_or(MalType::Nil));
+    Ok(MalType::Dict(hm))
+}
+
+impl Interpreter{
+    // the message shown for an uncaught error, with the message of a thrown ex-info
+    pub fn runtime_error(&self,err_str:String)->String{
+        if err_str == "Throwed an error."{
+            if let MalType::Dict(ref hm) = self.error{
+                if is_ex_info(&self.error){
+                    let message = match hm.get(MESSAGE_KEY){
+                        Some(MalType::Str(s)) => s.clone(),
+                        _ => String::new(),
+                    };
+                    return match hm.get(DATA_KEY){
+                        Some(MalType::Nil) | None => format!("Runtime error: {}",message),
+                        Some(data) => format!("Runtime error: {} {}",message,data.to_string(true)),
+                    };
+                }
+            }
+        }
+        format!("Runtime error: {}",err_str)
+    }
+}
+
+// the data of ex-info, or nil for other values
+pub fn mal_ex_data(xs:Vec<MalType>)->Result<MalType,String>{
+    if xs.len() != 1{
+        return Err(format!(
+            "The function ex-data needs exactly 1 arguments, we got {}.",xs.len()));
+    }
+
+    match xs[0]{
+        MalType::Dict(ref hm) if is_ex_info(&xs[0]) =>
+            Ok(hm.get(DATA_KEY).cloned().unwrap_or(MalType::Nil)),
+        _ => Ok(MalType::Nil),
+    }
+}
+
+// the message of ex-info or an error of the interpreter, or nil for other values
+pub fn mal_ex_message(xs:Vec<MalType>)->Result<MalType,String>{
+    if xs.len() != 1{
+        return Err(format!(
+            "The function ex-message needs exactly 1 arguments, we got {}.",xs.len()));
+    }
+
+    match xs[0]{
+        MalType::Dict(ref hm) if is_ex_info(&xs[0]) =>
+            Ok(hm.get(MESSAGE_KEY).cloned().unwrap_or(MalType::Nil)),
+        MalType::Str(_) => Ok(xs[0].clone()),
+        _ => Ok(MalType::Nil),
+    }
+}
+
+#[test]
+fn test_exception_0(){
+    let mut lisp = Interpreter::new();
+    lisp.rep("(def! log (atom []))".to_string()).unwrap();
+
+    let code = "(try* (throw (ex-info \"bad\" {:n 1}))
+                  (catch* :error e :error)
+                  (catch* :ex-info e (get (ex-data e) :n))
+                  (finally* (reset! log :done)))";
+    assert_eq!(lisp.rep(code.to_string()),Ok(MalType::Integer(1)));
+    assert_eq!(lisp.rep("@log".to_string()).unwrap(),MalType::Keyword(":done".to_string()));
+
+    let code = "(try* (nth [] 1) (catch* (fn* [e] (= \"str\" (type-str e))) e (ex-message e)))";
+    assert!(lisp.rep(code.to_string()).is_ok());
+
+    let code = "(try* (throw 1) (catch* :error e 2) (finally* (reset! log 3)))";
+    assert!(lisp.rep(code.to_string()).is_err());
+    assert_eq!(lisp.rep("@log".to_string()),Ok(MalType::Integer(3)));
+
+    let code = "(try* (try* (throw :original)
+                        (finally* (try* (throw :inner) (catch* e nil))))
+                  (catch* e e))";
+    assert_eq!(lisp.rep(code.to_string()),Ok(MalType::Keyword(":original".to_string())));
+
+    let code = "(throw (ex-info \"bad\" {:n 1}))";
+    assert_eq!(lisp.rep(code.to_string()),Err("Runtime error: bad {:n 1}".to_string()));
+    assert_eq!(lisp.rep("(throw (ex-info \"bad\"))".to_string()),Err("Runtime error: bad".to_string()));
+}
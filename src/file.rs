@@ -0,0 +1,216 @@
+use interpreter::Interpreter;
+use types::MalType;
+use std::fs::{File,OpenOptions};
+use std::io::BufReader;
+use std::io::prelude::*;
+
+// the file of MalType::File(n), which is self.files[n] until it is closed
+pub enum FileHandle{
+    Reader(BufReader<File>),
+    // writes are not buffered so that nothing is lost at exit
+    Writer(File),
+}
+
+impl Interpreter{
+    // (open path) or (open path :read), (open path :write), (open path :append)
+    pub fn mal_open(&mut self,xs:Vec<MalType>)->Result<MalType,String>{
+        if xs.len() != 1 && xs.len() != 2{
+            return Err(format!(
+                "The function open needs 1 or 2 arguments, we got {}.",xs.len()));
+        }
+        let filename = match xs[0]{
+            MalType::Str(ref s) => s.clone(),
+            _ => return Err(format!(
+                "The first argument of open must be string, we got {}.",xs[0].to_string(true))),
+        };
+        let mode = match xs.get(1){
+            None => ":read".to_string(),
+            Some(MalType::Keyword(k)) => k.clone(),
+            Some(x) => return Err(format!(
+                "The mode of open must be keyword, we got {}.",x.to_string(true))),
+        };
+
+        let handle = match mode.as_str(){
+            ":read" => File::open(&filename).map(|f| FileHandle::Reader(BufReader::new(f))),
+            ":write" => File::create(&filename).map(FileHandle::Writer),
+            ":append" => OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(&filename)
+                .map(FileHandle::Writer),
+            _ => return Err(format!("Unknown mode of open: {}",mode)),
+        };
+
+        match handle{
+            Ok(h) => {
+                let n = self.next_file;
+                self.next_file += 1;
+                self.files.insert(n,h);
+                Ok(MalType::File(n))
+            },
+            Err(_) => Err(format!("Cannot open file {}.",filename)),
+        }
+    }
+
+    // the next line without the line break, or nil at the end of the file
+    pub fn file_read_line(&mut self,n:usize)->Result<MalType,String>{
+        let reader = match self.files.get_mut(&n){
+            Some(FileHandle::Reader(r)) => r,
+            Some(FileHandle::Writer(_)) => return Err(format!("The file is not opened for reading.")),
+            None => return Err(format!("The file is closed.")),
+        };
+
+        let mut line = String::new();
+        match reader.read_line(&mut line){
+            Ok(0) => Ok(MalType::Nil),
+            Ok(_) => {
+                if line.ends_with('\n'){
+                    line.pop();
+                    if line.ends_with('\r'){
+                        line.pop();
+                    }
+                }
+                Ok(MalType::Str(line))
+            },
+            Err(_) => Err(format!("Cannot read the file.")),
+        }
+    }
+
+    // (write f x ...) writes the arguments like str
+    pub fn mal_write(&mut self,mut xs:Vec<MalType>)->Result<MalType,String>{
+        if xs.len() < 1{
+            return Err(format!(
+                "The function write needs at least 1 arguments, we got {}.",xs.len()));
+        }
+        let n = match xs.remove(0){
+            MalType::File(n) => n,
+            x => return Err(format!(
+                "The first argument of write must be file, we got {}.",x.to_string(true))),
+        };
+        let xs = match self.realize_sequence(xs){
+            Ok(v) => v,
+            Err(e) => return Err(e),
+        };
+        let s : String = xs.iter().map(|x| x.to_string(false)).collect();
+
+        let writer = match self.files.get_mut(&n){
+            Some(FileHandle::Writer(w)) => w,
+            Some(FileHandle::Reader(_)) => return Err(format!("The file is not opened for writing.")),
+            None => return Err(format!("The file is closed.")),
+        };
+        match writer.write_all(s.as_bytes()){
+            Ok(_) => Ok(MalType::Nil),
+            Err(_) => Err(format!("Cannot write the file.")),
+        }
+    }
+
+    // closing a closed file does nothing
+    pub fn mal_close(&mut self,xs:Vec<MalType>)->Result<MalType,String>{
+        if xs.len() != 1{
+            return Err(format!(
+                "The function close needs exactly 1 arguments, we got {}.",xs.len()));
+        }
+        match xs[0]{
+            MalType::File(n) => {
+                self.close_file(n);
+                Ok(MalType::Nil)
+            },
+            _ => Err(format!(
+                "The argument of close must be file, we got {}.",xs[0].to_string(true))),
+        }
+    }
+
+    fn close_file(&mut self,n:usize){
+        self.files.remove(&n);
+    }
+
+    // (with-open [f (open "a.txt")] body ...)
+    // The files are closed in reverse order even if the body fails.
+    pub fn mal_with_open(&mut self,mut xs:Vec<MalType>)->Result<MalType,String>{
+        if xs.len() < 1{
+            return Err(format!(
+                "The function with-open needs at least 1 arguments, we got {}.",xs.len()));
+        }
+        let bindings = match xs.remove(0){
+            MalType::Vector(v) | MalType::List(v) => v,
+            x => return Err(format!(
+                "The first argument of with-open must be vector, we got {}.",x.to_string(true))),
+        };
+        if bindings.len() % 2 != 0{
+            return Err(format!("The bindings of with-open must be pairs of name and file."));
+        }
+
+        let env_level = self.env.get_level();
+        self.env.let_start();
+
+        let mut opened = vec![];
+        let mut result = Ok(MalType::Nil);
+        for pair in bindings.chunks(2){
+            let name = match pair[0]{
+                MalType::Identifier(ref s) => s.clone(),
+                _ => {
+                    result = Err(format!(
+                        "The name of with-open must be symbol, we got {}.",pair[0].to_string(true)));
+                    break;
+                },
+            };
+            match self.eval(pair[1].clone()){
+                Ok(MalType::File(n)) => {
+                    opened.push(n);
+                    self.env.set(name,MalType::File(n));
+                },
+                Ok(v) => {
+                    result = Err(format!(
+                        "The value of {} in with-open must be file, we got {}.",name,v.to_string(true)));
+                    break;
+                },
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                },
+            }
+        }
+
+        if result.is_ok(){
+            for x in xs{
+                result = self.eval(x);
+                if result.is_err(){
+                    break;
+                }
+            }
+        }
+
+        for n in opened.into_iter().rev(){
+            self.close_file(n);
+        }
+        while self.env.get_level() != env_level{
+            self.env.let_end();
+        }
+
+        result
+    }
+}
+
+#[test]
+fn test_with_open_0(){
+    let path = std::env::temp_dir().join(format!("simple_lisp_file_{}.txt",std::process::id()));
+    let path = path.to_string_lossy().replace('\\',"/");
+    let mut lisp = Interpreter::new();
+    lisp.load_prelude().unwrap();
+
+    // lazy sequences are written like str
+    let code = format!("(with-open [w (open \"{}\" :write)] (write w \"a\\nb\" (map inc [0]) \"\\n\"))",path);
+    assert_eq!(lisp.rep(code),Ok(MalType::Nil));
+
+    let code = format!("(def! lines (atom [])) \
+        (try* (with-open [r (open \"{}\")] \
+                (reset! lines [(read-line r) (read-line r) (read-line r)]) \
+                (throw r)) \
+              (catch* r (read-line r)))",path);
+    assert_eq!(lisp.rep(code),Err("Runtime error: The file is closed.".to_string()));
+    assert_eq!(lisp.rep("@lines".to_string()).unwrap().to_string(true),"[\"a\" \"b(1)\" nil]");
+
+    assert!(lisp.files.is_empty());
+
+    std::fs::remove_file(path).unwrap();
+}
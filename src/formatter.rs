@@ -0,0 +1,195 @@
+use cst::{CstNode,read_cst};
+
+const WIDTH : usize = 80;
+const INDENT : usize = 2;
+
+// a form in the source code, which keeps comments and original spellings
+enum Node{
+    Atom(String),
+    Seq(String,String,Vec<Item>), // open, close, items
+    Prefix(String,Box<Node>),     // ', `, ~, ~@ and @
+    Comment(String),
+}
+
+struct Item{
+    node : Node,
+    // a blank line was before the node
+    blank_before : bool,
+    // the comment was on the same line as the previous token
+    trailing : bool,
+}
+
+// Formats the code in the canonical style.
+// Forms which fit in the line are printed on one line. The others put
+// the body of special forms like def! and fn* on the following lines with
+// 2 spaces, and align the arguments of function calls under the first one.
+pub fn format_source(code:&str)->Result<String,String>{
+    let items = match read_cst(code){
+        Ok(nodes) => to_items(&nodes),
+        Err(e) => return Err(e),
+    };
+
+    let mut out = String::new();
+    for (i,item) in items.iter().enumerate(){
+        if i > 0{
+            if item.trailing{
+                out.push(' ');
+            }else{
+                out.push('\n');
+                if item.blank_before{
+                    out.push('\n');
+                }
+            }
+        }
+        out.push_str(&render(&item.node,0));
+    }
+    if !out.is_empty(){
+        out.push('\n');
+    }
+
+    Ok(out)
+}
+
+// keeps the comments and where blank lines are from the trivia
+fn to_items(nodes:&[CstNode])->Vec<Item>{
+    let mut items = vec![];
+    let mut newlines = 0;
+
+    for node in nodes{
+        let node = match node{
+            CstNode::Whitespace(s) => {
+                newlines += s.matches('\n').count();
+                continue;
+            },
+            CstNode::Comma => continue,
+            CstNode::Comment(s) => Node::Comment(s.trim_end().to_string()),
+            CstNode::Atom(s,_) => Node::Atom(s.clone()),
+            CstNode::Seq(open,children,close) =>
+                Node::Seq(open.clone(),close.clone(),to_items(children)),
+            CstNode::Prefix(mark,children) => {
+                // comments between the mark and the form are moved before the form
+                let mut form = to_items(children);
+                let last = form.pop().unwrap();
+                for comment in form{
+                    items.push(comment);
+                }
+                Node::Prefix(mark.clone(),Box::new(last.node))
+            },
+        };
+        let is_comment = matches!(node,Node::Comment(_));
+
+        items.push(Item{
+            node,
+            blank_before : newlines >= 2,
+            trailing : is_comment && newlines == 0,
+        });
+        newlines = 0;
+    }
+
+    items
+}
+
+// the number of arguments which stay on the line of the special form
+fn body_start(head:&str)->Option<usize>{
+    match head{
+        "def!" | "def-!" | "defmacro!" | "fn*" | "let*" | "if" | "catch*" | "with-open" => Some(1),
+        "do" | "try*" | "finally*" | "lazy-seq" | "cond" => Some(0),
+        _ => None,
+    }
+}
+
+fn has_comment(node:&Node)->bool{
+    match node{
+        Node::Comment(_) => true,
+        Node::Prefix(_,node) => has_comment(node),
+        Node::Seq(_,_,items) => items.iter().any(|item| has_comment(&item.node)),
+        Node::Atom(_) => false,
+    }
+}
+
+fn render_flat(node:&Node)->String{
+    match node{
+        Node::Atom(s) | Node::Comment(s) => s.clone(),
+        Node::Prefix(p,node) => format!("{}{}",p,render_flat(node)),
+        Node::Seq(open,close,items) => {
+            let xs : Vec<String> = items.iter().map(|item| render_flat(&item.node)).collect();
+            format!("{}{}{}",open,xs.join(" "),close)
+        },
+    }
+}
+
+// the column where the next character of out is printed
+fn current_column(out:&str,column:usize)->usize{
+    match out.rfind('\n'){
+        Some(i) => out[i+1..].chars().count(),
+        None => column + out.chars().count(),
+    }
+}
+
+// renders the node which starts at the column
+fn render(node:&Node,column:usize)->String{
+    let (open,close,items) = match node{
+        Node::Atom(s) | Node::Comment(s) => return s.clone(),
+        Node::Prefix(p,node) => return format!("{}{}",p,render(node,column+p.chars().count())),
+        Node::Seq(open,close,items) => (open,close,items),
+    };
+
+    let flat = render_flat(node);
+    if !has_comment(node) && column + flat.chars().count() <= WIDTH{
+        return flat;
+    }
+
+    // how many items are on the first line, and where the others start
+    let head = match items.first(){
+        Some(Item{ node : Node::Atom(s), .. }) if open == "(" => Some(s.clone()),
+        _ => None,
+    };
+    let (inline,rest_column) = match head{
+        Some(ref h) => match body_start(h){
+            Some(n) => (1+n,column+INDENT),
+            None => (2,column+1+h.chars().count()+1),
+        },
+        None => (1,column+1),
+    };
+
+    let mut out = open.clone();
+    let mut must_break = false;
+    for (i,item) in items.iter().enumerate(){
+        let is_comment = matches!(item.node,Node::Comment(_));
+
+        if item.trailing && i > 0{
+            out.push(' ');
+        }else if i > 0 && (i >= inline || must_break || is_comment){
+            if item.blank_before{
+                out.push('\n');
+            }
+            out.push('\n');
+            out.push_str(&" ".repeat(rest_column));
+        }else if i > 0{
+            out.push(' ');
+        }
+
+        let col = current_column(&out,column);
+        out.push_str(&render(&item.node,col));
+        must_break = is_comment;
+    }
+
+    // the close bracket cannot follow a line comment
+    if must_break{
+        out.push('\n');
+        out.push_str(&" ".repeat(rest_column));
+    }
+    out.push_str(close);
+
+    out
+}
+
+#[test]
+fn test_formatter_0(){
+    let src = "; adds one\n(def! inc2 (fn* [x]   ; x is an integer\n (+ x 1)))\n\n\n(inc2 1)";
+    let expected = "; adds one\n(def! inc2\n  (fn* [x] ; x is an integer\n    (+ x 1)))\n\n(inc2 1)\n";
+
+    let formatted = format_source(src).unwrap();
+    assert_eq!(formatted,expected);
+    assert_eq!(format_source(&formatted).unwrap(),formatted);
+}
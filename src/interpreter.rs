@@ -1,15 +1,60 @@
 use env::Env;
-use types::MalType;
+use namespace::Namespace;
+use file::FileHandle;
+use line_editor::LineEditor;
+use types::{MalType,LazySeqState};
 use std::io;
-use std::fs::File;
-use std::io::BufReader;
 use std::io::prelude::*;
-use std::collections::HashMap;
+use std::collections::{HashMap,HashSet};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool,Ordering};
+use rustyline::error::ReadlineError;
+
+// lib.mal is built into the binary so that scripts run from any directory
+const PRELUDE : &str = include_str!("lib.mal");
+
+// the recent results and the last error in the REPL
+pub const HISTORY_VARS : [&str;4] = ["*1","*2","*3","*e"];
+
+// set by the SIGINT handler and checked by eval
+pub static INTERRUPTED : AtomicBool = AtomicBool::new(false);
+
+// how a program finished
+#[derive(PartialEq, Debug)]
+pub enum Outcome{
+    Value(MalType),
+    Error(String),
+    Exit(i32),
+}
 
 pub struct Interpreter{
     pub env : Env,
     pub atoms : HashMap<usize,MalType>,
+    pub lazy_seqs : HashMap<usize,LazySeqState>,
+    // the numbers of the lazy sequences which are collected, reused first
+    pub free_lazy_seqs : Vec<usize>,
+    pub next_lazy_seq : usize,
     pub error : MalType,
+    pub editor : Option<LineEditor>,
+    // set by (exit n) while the evaluation is unwinding
+    pub exit_code : Option<i32>,
+    pub exit_hooks : Vec<MalType>,
+    // the names defined by lib.mal, which :env does not show
+    pub prelude_names : Vec<String>,
+    // the last file loaded at the top level, which :reload loads again
+    pub loaded_file : Option<String>,
+    // the error of the last rep, which the REPL binds to *e
+    pub last_error : MalType,
+    // the namespaces other than "user", whose definitions are in the root of env
+    pub namespaces : HashMap<String,Namespace>,
+    // the canonical paths of the files being loaded, innermost last
+    pub loading : Vec<PathBuf>,
+    // the canonical paths of the files load-file does not evaluate again
+    pub loaded : HashSet<PathBuf>,
+    // the number of the last symbol gensym made
+    pub gensym_counter : usize,
+    pub files : HashMap<usize,FileHandle>,
+    pub next_file : usize,
 }
 
 impl Interpreter{
@@ -17,17 +62,33 @@ impl Interpreter{
         Interpreter{
             env : Env::new(),
             atoms : HashMap::new(),
+            lazy_seqs : HashMap::new(),
+            free_lazy_seqs : vec![],
+            next_lazy_seq : 0,
             error : MalType::Nil,
+            editor : None,
+            exit_code : None,
+            exit_hooks : vec![],
+            prelude_names : vec![],
+            loaded_file : None,
+            last_error : MalType::Nil,
+            namespaces : HashMap::new(),
+            loading : vec![],
+            loaded : HashSet::new(),
+            gensym_counter : 0,
+            files : HashMap::new(),
+            next_file : 0,
         }
     }
 }
 
 impl Interpreter{
-    fn rep(&mut self,s:String)->Result<MalType,String>{
+    pub fn rep(&mut self,s:String)->Result<MalType,String>{
         let asts = self.read(s); // Vec<Result<MalType,String>> 
         let mut last : Result<MalType,String> = Ok(MalType::Nil);
 
         if let Err(e) = asts{
+            self.last_error = MalType::Str(e.clone());
             last = Err(format!("Parse error: {}",e));
         }else{
             let asts = asts.unwrap();
@@ -35,7 +96,8 @@ impl Interpreter{
                 last = self.eval(ast);
 
                 if let Err(e) = last{
-                    last = Err(format!("Runtime error: {}",e));
+                    self.last_error = self.error_value(e.clone());
+                    last = Err(self.runtime_error(e));
                     break;
                 }
             }
@@ -43,28 +105,176 @@ impl Interpreter{
 
         last
     }
+
+    // rep for a whole program read at the top level, such as a script.
+    // The lazy sequences no one refers to are freed after each form.
+    pub fn rep_program(&mut self,s:String)->Result<MalType,String>{
+        let asts = match self.read(s){
+            Ok(v) => v,
+            Err(e) => {
+                self.last_error = MalType::Str(e.clone());
+                return Err(format!("Parse error: {}",e));
+            },
+        };
+
+        let mut last = MalType::Nil;
+        for ast in asts{
+            last = match self.eval(ast){
+                Ok(v) => v,
+                Err(e) => {
+                    self.last_error = self.error_value(e.clone());
+                    return Err(self.runtime_error(e));
+                },
+            };
+            self.collect_lazy_seqs(&last);
+        }
+
+        Ok(last)
+    }
 }
 
 impl Interpreter{
-    pub fn repl_loop(&mut self){
+    pub fn run(&mut self,code:String)->Outcome{
+        // the value is realized here so that (exit n) or an error in a lazy
+        // sequence becomes the outcome instead of being printed
+        let result = match self.rep(code){
+            Ok(v) => match self.realize_all(v){
+                Ok(v) => Ok(v),
+                Err(e) => {
+                    self.last_error = self.error_value(e.clone());
+                    Err(self.runtime_error(e))
+                },
+            },
+            Err(e) => Err(e),
+        };
+        if let Ok(ref v) = result{
+            self.collect_lazy_seqs(v);
+        }else{
+            self.collect_lazy_seqs(&MalType::Nil);
+        }
+        self.to_outcome(result)
+    }
+
+    pub fn to_outcome(&mut self,result:Result<MalType,String>)->Outcome{
+        match result{
+            Ok(v) => Outcome::Value(v),
+            Err(e) => match self.exit_code.take(){
+                Some(code) => Outcome::Exit(code),
+                None => Outcome::Error(e),
+            },
+        }
+    }
+
+    // calls the functions registered by at-exit, the last registered first
+    pub fn run_exit_hooks(&mut self){
+        while let Some(f) = self.exit_hooks.pop(){
+            if let Err(e) = self.call_function(f,vec![]){
+                if self.exit_code.take().is_none(){
+                    eprintln!("Runtime error in at-exit: {}",e);
+                }
+            }
+        }
+    }
+
+    // Without the line editor, the REPL reads lines from stdin.
+    // It replaces the SIGINT handler, so it must start before ctrlc sets one.
+    pub fn start_line_editor(&mut self){
+        self.editor = match LineEditor::new(){
+            Ok(v) => Some(v),
+            Err(e) => {
+                eprintln!("{}",e);
+                None
+            },
+        };
+    }
+
+    // returns the exit status when the input reaches EOF
+    pub fn repl_loop(&mut self)->i32{
+        for name in HISTORY_VARS.iter(){
+            self.env.set_root(name.to_string(),MalType::Nil);
+        }
+
         loop{
-            let code = self.read_code();
-            let last = self.rep(code);
-            self.print(last);
+            let code = match self.read_code(){
+                Some(v) => v,
+                None => {
+                    eprintln!("Have a nice day!");
+                    return 0;
+                },
+            };
+            // Ctrl-C pressed after the last evaluation must not stop this one
+            INTERRUPTED.store(false,Ordering::SeqCst);
+            let outcome = match self.run_command(&code){
+                // the command has printed its result
+                Some(Outcome::Value(_)) => continue,
+                Some(v) => v,
+                None => self.run(code),
+            };
+            match outcome{
+                Outcome::Value(v) => {
+                    self.push_history(v.clone());
+                    self.print(Ok(v));
+                },
+                Outcome::Error(e) => {
+                    let error = self.last_error.clone();
+                    self.env.set_root("*e".to_string(),error);
+                    self.print(Err(e));
+                },
+                Outcome::Exit(code) => return code,
+            }
+        }
+    }
+
+    // *3 <- *2 <- *1 <- value
+    fn push_history(&mut self,value:MalType){
+        for i in (1..3).rev(){
+            let prev = self.env.get(&format!("*{}",i)).unwrap_or(MalType::Nil);
+            self.env.set_root(format!("*{}",i+1),prev);
         }
+        self.env.set_root("*1".to_string(),value);
     }
 
-    fn read_code(&self) -> String{
+    // returns None at EOF
+    fn read_code(&mut self) -> Option<String>{
         let mut s = String::new();
 
         loop{
-            let new_line = self.read_line();
-            if new_line.trim() == ""{
-                return s
+            // the prompt shows the current namespace
+            let prompt = if s.trim() == ""{
+                format!("{}> ",self.current_ns())
             }else{
-                s = format!("{}{}",s,new_line);
+                format!("{}  ... "," ".repeat(self.current_ns().len().saturating_sub(4)))
+            };
+            match self.prompt_line(&prompt){
+                Ok(new_line) => s = format!("{}{}",s,new_line),
+                // Ctrl-C at the prompt discards the input
+                Err(ReadlineError::Interrupted) => s = String::new(),
+                Err(_) => return None,
             }
-        } 
+
+            if s.trim() != "" && !self.is_incomplete(s.clone()){
+                return Some(s)
+            }
+        }
+    }
+
+    // reads a line with the line editor if it is available
+    pub fn prompt_line(&mut self,prompt:&str)->Result<String,ReadlineError>{
+        let names = self.env.names();
+
+        match self.editor{
+            Some(ref mut editor) => editor.read_line(prompt,names),
+            None => {
+                print!("{}",prompt);
+                let _ = io::stdout().flush();
+                let mut new_line = String::new();
+                match io::stdin().read_line(&mut new_line){
+                    Ok(0) => Err(ReadlineError::Eof),
+                    Ok(_) => Ok(new_line),
+                    Err(e) => Err(ReadlineError::Io(e)),
+                }
+            },
+        }
     }
 
     pub fn read_line(&self)->String{
@@ -75,18 +285,42 @@ impl Interpreter{
         new_line
     }
 
-    pub fn load_file(&mut self,filename:String)->Result<MalType,String>{
-        let file = match File::open(filename.clone()){
-            Ok(v) => v,
-            Err(_) => return Err(format!("Cannot open file {}.",filename)),
-        };
-        let mut buf_reader = BufReader::new(file);
+    pub fn load_prelude(&mut self)->Result<MalType,String>{
+        let result = self.rep(PRELUDE.to_string());
+        self.prelude_names = self.env.names();
+        result
+    }
+
+    pub fn set_argv(&mut self,args:Vec<String>){
+        let argv = args.into_iter().map(MalType::Str).collect();
+        self.env.set("*ARGV*".to_string(),MalType::List(argv));
+    }
+
+    // evaluates the whole standard input as a program
+    pub fn load_stdin(&mut self)->Result<MalType,String>{
         let mut code = String::new();
-        match buf_reader.read_to_string(&mut code){
+        match io::stdin().read_to_string(&mut code){
             Ok(_) => {},
-            Err(_) => return Err(format!("Cannot read file {}.",filename)),
+            Err(_) => return Err(format!("Cannot read standard input.")),
         }
 
-        self.rep(code)
+        self.rep_program(code)
     }
 }
+
+#[test]
+fn test_exit_0(){
+    let mut lisp = Interpreter::new();
+    lisp.load_prelude().unwrap();
+
+    let code = "(def! a (atom 0)) (at-exit (fn* [] (reset! a 1))) (try* (exit 3) (catch* e 0))";
+    assert_eq!(lisp.run(code.to_string()),Outcome::Exit(3));
+
+    lisp.run_exit_hooks();
+    assert_eq!(lisp.run("@a".to_string()),Outcome::Value(MalType::Integer(1)));
+
+    // the exit in a lazy sequence happens when the result is realized
+    assert_eq!(lisp.run("(map (fn* [x] (exit 6)) [1])".to_string()),Outcome::Exit(6));
+    assert!(lisp.run("(exit 256)".to_string()) != Outcome::Exit(0));
+    assert_eq!(lisp.exit_code,None);
+}
@@ -0,0 +1,477 @@
+use interpreter::Interpreter;
+use types::{MalType,BuiltInFunction,LazySeqState};
+use core::{mal_nth,mal_eq,mal_count};
+use std::collections::HashSet;
+
+impl Interpreter{
+    pub fn mal_lazy_seq(&mut self,mut xs:Vec<MalType>)->Result<MalType,String>{
+        // (lazy-seq a b) -> (fn* [] (do a b))
+        let body = if xs.len() == 0{
+            MalType::Nil
+        }else if xs.len() == 1{
+            xs.remove(0)
+        }else{
+            xs.insert(0,MalType::BuiltInFunction(BuiltInFunction::Do));
+            MalType::List(xs)
+        };
+
+        let thunk = match self.mal_fn(vec![MalType::List(vec![]),body]){
+            Ok(v) => v,
+            Err(e) => return Err(e),
+        };
+
+        Ok(self.new_lazy_seq(LazySeqState::Thunk(thunk)))
+    }
+
+    pub fn mal_cons(&mut self,x:MalType,xs:MalType)->Result<MalType,String>{
+        match xs{
+            MalType::List(mut v) => {
+                v.insert(0,x);
+                Ok(MalType::List(v))
+            },
+            MalType::Vector(mut v) => {
+                v.insert(0,x);
+                Ok(MalType::Vector(v))
+            },
+            MalType::Nil => {
+                Ok(MalType::List(vec![x]))
+            },
+            MalType::LazySeq(_) => {
+                Ok(self.new_lazy_seq(LazySeqState::Cons(x,xs)))
+            },
+            _ => Err(format!(
+                "The second argument of cons must be sequence, we got {}.",
+                xs.to_string(true)))
+        }
+    }
+
+    pub fn lazy_nth(&mut self,at:usize,n:MalType)->Result<MalType,String>{
+        let mut n = match n.unwrap_integer(){
+            Some(v) => v,
+            None => return Err(format!("The second argument of nth must be integer.")),
+        };
+        if n < 0{
+            return Err(format!(
+                "The second argument of nth must be 0 or positive number, we got {}.",n));
+        }
+
+        let mut x = MalType::LazySeq(at);
+        loop{
+            let at = match x{
+                MalType::LazySeq(at) => at,
+                // the tail of a lazy sequence can be an ordinary list
+                _ => return mal_nth(x,MalType::Integer(n)),
+            };
+
+            match self.realize_lazy_seq(at){
+                Ok(LazySeqState::Cons(first,rest)) => {
+                    if n == 0{
+                        return Ok(first);
+                    }
+                    n -= 1;
+                    x = rest;
+                },
+                Ok(_) => return Err(format!("The index is out of bounds.")),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    pub fn lazy_rest(&mut self,at:usize)->Result<MalType,String>{
+        match self.realize_lazy_seq(at){
+            Ok(LazySeqState::Cons(_,rest)) => Ok(rest),
+            Ok(_) => Ok(MalType::List(vec![])),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn lazy_to_seq(&mut self,at:usize)->Result<MalType,String>{
+        match self.realize_lazy_seq(at){
+            Ok(LazySeqState::Cons(_,_)) => Ok(MalType::LazySeq(at)),
+            Ok(_) => Ok(MalType::Nil),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn lazy_count(&mut self,at:usize)->Result<MalType,String>{
+        let mut n = 0;
+        let mut x = MalType::LazySeq(at);
+
+        loop{
+            let at = match x{
+                MalType::LazySeq(at) => at,
+                _ => return match mal_count(x){
+                    Ok(MalType::Integer(m)) => Ok(MalType::Integer(n+m)),
+                    v => v,
+                },
+            };
+
+            match self.realize_lazy_seq(at){
+                Ok(LazySeqState::Cons(_,rest)) => {
+                    n += 1;
+                    x = rest;
+                },
+                Ok(_) => return Ok(MalType::Integer(n)),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    pub fn lazy_eq(&mut self,mut xs:Vec<MalType>)->Result<MalType,String>{
+        let b = xs.pop().unwrap();
+        let a = xs.pop().unwrap();
+
+        match self.seq_eq(a,b){
+            Ok(v) => Ok(MalType::Bool(v)),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Sequences are compared element by element, and the comparison stops
+    // at the first difference, so an infinite sequence is not realized.
+    fn seq_eq(&mut self,a:MalType,b:MalType)->Result<bool,String>{
+        let is_seq = |x:&MalType| x.is_sequence() || x.is_lazy_seq();
+
+        let walk = a.is_lazy_seq() || b.is_lazy_seq() || (a.is_sequence() && b.is_sequence());
+        if !walk{
+            // a dict can have lazy sequences in its values
+            let ys = match self.realize_sequence(vec![a,b]){
+                Ok(v) => v,
+                Err(e) => return Err(e),
+            };
+            return match mal_eq(ys){
+                Ok(v) => Ok(v == MalType::Bool(true)),
+                Err(e) => Err(e),
+            };
+        }
+        if !is_seq(&a) || !is_seq(&b){
+            return Ok(false);
+        }
+
+        let (mut a,mut i) = (a,0);
+        let (mut b,mut j) = (b,0);
+        loop{
+            let x = match self.seq_next(&mut a,&mut i){
+                Ok(v) => v,
+                Err(e) => return Err(e),
+            };
+            let y = match self.seq_next(&mut b,&mut j){
+                Ok(v) => v,
+                Err(e) => return Err(e),
+            };
+            match (x,y){
+                (None,None) => return Ok(true),
+                (Some(x),Some(y)) => match self.seq_eq(x,y){
+                    Ok(true) => {},
+                    v => return v,
+                },
+                _ => return Ok(false),
+            }
+        }
+    }
+
+    // the element of the sequence x at the index i, moving to the next one.
+    // After a lazy sequence, x becomes its rest.
+    fn seq_next(&mut self,x:&mut MalType,i:&mut usize)->Result<Option<MalType>,String>{
+        let at = match x{
+            MalType::LazySeq(at) => *at,
+            MalType::List(v) | MalType::Vector(v) => {
+                *i += 1;
+                return Ok(v.get(*i-1).cloned());
+            },
+            _ => return Ok(None),
+        };
+
+        match self.realize_lazy_seq(at){
+            Ok(LazySeqState::Cons(first,rest)) => {
+                *x = rest;
+                *i = 0;
+                Ok(Some(first))
+            },
+            Ok(_) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    // A lazy sequence becomes a list of its elements, which are not realized.
+    // This is for the built-in functions which take lists and vectors.
+    pub fn lazy_to_list(&mut self,x:MalType)->Result<MalType,String>{
+        if !x.is_lazy_seq(){
+            return Ok(x);
+        }
+
+        let mut ys = vec![];
+        let (mut x,mut i) = (x,0);
+        loop{
+            match self.seq_next(&mut x,&mut i){
+                Ok(Some(y)) => ys.push(y),
+                Ok(None) => return Ok(MalType::List(ys)),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    pub fn lazy_to_lists(&mut self,xs:Vec<MalType>)->Result<Vec<MalType>,String>{
+        let mut ys = vec![];
+
+        for x in xs{
+            match self.lazy_to_list(x){
+                Ok(v) => ys.push(v),
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(ys)
+    }
+
+    // Replaces every lazy sequence in x with a list of its elements.
+    // This never returns for infinite sequences.
+    pub fn realize_all(&mut self,x:MalType)->Result<MalType,String>{
+        match x{
+            MalType::LazySeq(_) => {
+                let mut ys = vec![];
+                let mut x = x;
+
+                loop{
+                    let at = match x{
+                        MalType::LazySeq(at) => at,
+                        _ => {
+                            for y in x.unwrap_sequence().unwrap_or(vec![]){
+                                match self.realize_all(y){
+                                    Ok(v) => ys.push(v),
+                                    Err(e) => return Err(e),
+                                }
+                            }
+                            break;
+                        }
+                    };
+
+                    match self.realize_lazy_seq(at){
+                        Ok(LazySeqState::Cons(first,rest)) => {
+                            match self.realize_all(first){
+                                Ok(v) => ys.push(v),
+                                Err(e) => return Err(e),
+                            }
+                            x = rest;
+                        },
+                        Ok(_) => break,
+                        Err(e) => return Err(e),
+                    }
+                }
+
+                Ok(MalType::List(ys))
+            },
+            MalType::List(xs) => {
+                match self.realize_sequence(xs){
+                    Ok(v) => Ok(MalType::List(v)),
+                    Err(e) => Err(e),
+                }
+            },
+            MalType::Vector(xs) => {
+                match self.realize_sequence(xs){
+                    Ok(v) => Ok(MalType::Vector(v)),
+                    Err(e) => Err(e),
+                }
+            },
+            MalType::Dict(mut hm) => {
+                for (_,val) in hm.iter_mut(){
+                    *val = match self.realize_all(val.clone()){
+                        Ok(v) => v,
+                        Err(e) => return Err(e),
+                    };
+                }
+                Ok(MalType::Dict(hm))
+            },
+            _ => Ok(x),
+        }
+    }
+
+    pub fn realize_sequence(&mut self,xs:Vec<MalType>)->Result<Vec<MalType>,String>{
+        let mut ys = vec![];
+
+        for x in xs{
+            match self.realize_all(x){
+                Ok(v) => ys.push(v),
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(ys)
+    }
+
+    fn realize_lazy_seq(&mut self,at:usize)->Result<LazySeqState,String>{
+        // (lazy-seq (lazy-seq ...)) is realized by a loop instead of recursion
+        // so that filter or drop skipping many elements does not overflow the stack.
+        let mut chain = vec![];
+        let mut at = at;
+
+        let state = loop{
+            let thunk = match self.get_lazy_seq(at){
+                LazySeqState::Thunk(f) => f,
+                state => break state,
+            };
+            chain.push(at);
+
+            let value = match self.call_function(thunk,vec![]){
+                Ok(v) => v,
+                Err(e) => return Err(e),
+            };
+
+            match value{
+                MalType::LazySeq(next) => {
+                    if chain.contains(&next){
+                        return Err(format!("The lazy sequence refers to itself."));
+                    }
+                    at = next;
+                },
+                MalType::Nil => break LazySeqState::Empty,
+                MalType::List(mut v) => {
+                    if v.len() == 0{
+                        break LazySeqState::Empty;
+                    }
+                    let first = v.remove(0);
+                    break LazySeqState::Cons(first,MalType::List(v));
+                },
+                MalType::Vector(mut v) => {
+                    if v.len() == 0{
+                        break LazySeqState::Empty;
+                    }
+                    let first = v.remove(0);
+                    break LazySeqState::Cons(first,MalType::Vector(v));
+                },
+                _ => return Err(format!(
+                    "The body of lazy-seq must return sequence or nil, we got {}.",
+                    value.to_string(true))),
+            }
+        };
+
+        for at in chain{
+            self.lazy_seqs.insert(at,state.clone());
+        }
+
+        Ok(state)
+    }
+
+    fn new_lazy_seq(&mut self,state:LazySeqState)->MalType{
+        let at = match self.free_lazy_seqs.pop(){
+            Some(v) => v,
+            None => {
+                self.next_lazy_seq += 1;
+                self.next_lazy_seq - 1
+            },
+        };
+        self.lazy_seqs.insert(at,state);
+        MalType::LazySeq(at)
+    }
+
+    // Frees the lazy sequences which cannot be reached from the variables,
+    // atoms, namespaces and the given value.
+    // This must be called only between evaluations at the top level,
+    // because a sequence held only by a running evaluation is not seen here.
+    pub fn collect_lazy_seqs(&mut self,value:&MalType){
+        if self.lazy_seqs.is_empty(){
+            return;
+        }
+
+        let mut marked = HashSet::new();
+        {
+            let mut stack : Vec<&MalType> = vec![value,&self.error,&self.last_error];
+            stack.extend(self.env.values());
+            stack.extend(self.atoms.values());
+            stack.extend(self.exit_hooks.iter());
+            for ns in self.namespaces.values(){
+                stack.extend(ns.defs.values());
+            }
+
+            while let Some(x) = stack.pop(){
+                match x{
+                    MalType::LazySeq(at) => {
+                        if !marked.insert(*at){
+                            continue;
+                        }
+                        match self.lazy_seqs.get(at){
+                            Some(LazySeqState::Thunk(f)) => stack.push(f),
+                            Some(LazySeqState::Cons(first,rest)) => {
+                                stack.push(first);
+                                stack.push(rest);
+                            },
+                            _ => {},
+                        }
+                    },
+                    MalType::List(xs) | MalType::Vector(xs) => stack.extend(xs.iter()),
+                    MalType::Dict(hm) => stack.extend(hm.values()),
+                    MalType::Function(_,body,_,local_env,_,_) => {
+                        stack.push(body);
+                        stack.extend(local_env.values());
+                    },
+                    _ => {},
+                }
+            }
+        }
+
+        let unreachable : Vec<usize> = self.lazy_seqs
+            .keys()
+            .filter(|at| !marked.contains(*at))
+            .cloned()
+            .collect();
+        for at in unreachable{
+            self.lazy_seqs.remove(&at);
+            self.free_lazy_seqs.push(at);
+        }
+    }
+
+    fn get_lazy_seq(&self,at:usize)->LazySeqState{
+        match self.lazy_seqs.get(&at){
+            Some(v) => v.clone(),
+            None => LazySeqState::Empty,
+        }
+    }
+}
+
+#[test]
+fn test_lazy_seq_0(){
+    let mut lisp = Interpreter::new();
+    lisp.load_prelude().unwrap();
+
+    let ast = lisp.read("(take 3 (filter odd? (map inc (range))))".to_string())
+        .unwrap().remove(0);
+    let result = lisp.eval(ast).unwrap();
+    let result = lisp.realize_all(result).unwrap();
+
+    assert_eq!(result.to_string(true),"(1 3 5)");
+
+    // the sequences no variable refers to are freed after each run
+    lisp.run("(def! s (map inc (range 3)))".to_string());
+    for _ in 0..20{
+        lisp.run("(count (take 10 (map inc (range))))".to_string());
+    }
+    assert!(lisp.lazy_seqs.len() < 100);
+
+    // and after each form of a program such as a script
+    let program = "(count (take 10 (map inc (range)))) ".repeat(20);
+    assert_eq!(lisp.rep_program(program),Ok(MalType::Integer(10)));
+    assert!(lisp.lazy_seqs.len() < 100);
+    let s = lisp.rep("s".to_string()).unwrap();
+    assert_eq!(lisp.realize_all(s).unwrap().to_string(true),"(1 2 3)");
+}
+
+#[test]
+fn test_lazy_seq_1(){
+    let mut lisp = Interpreter::new();
+    lisp.load_prelude().unwrap();
+
+    // the sequence functions take lazy sequences like lists
+    let cases = [
+        ("(append (range 3) 9)","(0 1 2 9)"),
+        ("(concat [0] (map inc [1 2]))","(0 2 3)"),
+        ("(conj (range 3) 9)","(9 0 1 2)"),
+        ("(list? (map inc [1]))","true"),
+        ("(vec-to-list (filter odd? (range 4)))","(1 3)"),
+        ("(= (range) [1 2])","false"),
+        ("(= (map inc [0 1]) [1 2])","true"),
+    ];
+    for (code,expected) in cases.iter(){
+        let result = lisp.rep(code.to_string()).unwrap();
+        assert_eq!(lisp.realize_all(result).unwrap().to_string(true),*expected);
+    }
+}
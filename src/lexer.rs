@@ -1,10 +1,11 @@
 use types::{TokenKind,Token};
 
 pub struct Lexer{
-    source : String,
+    source : Vec<char>,
     index : usize,
     tokens : Vec<Token>,
     token_index : usize,
+    keep_comments : bool,
 }
 
 fn is_symbol_char(c:char)->bool{
@@ -23,19 +24,64 @@ fn is_symbol_char(c:char)->bool{
     || c == '}'
 }
 
+fn parse_integer(s:&str)->Option<i64>{
+    let (sign,s) = if let Some(rest) = s.strip_prefix('-'){
+        ("-",rest)
+    }else if let Some(rest) = s.strip_prefix('+'){
+        ("",rest)
+    }else{
+        ("",s)
+    };
+
+    let (radix,digits) = if s.starts_with("0x") || s.starts_with("0X"){
+        (16,&s[2..])
+    }else if s.starts_with("0o") || s.starts_with("0O"){
+        (8,&s[2..])
+    }else if s.starts_with("0b") || s.starts_with("0B"){
+        (2,&s[2..])
+    }else{
+        (10,s)
+    };
+
+    // underscores are allowed only between digits
+    if digits.len() == 0
+        || digits.starts_with('_')
+        || digits.ends_with('_')
+        || digits.contains("__"){
+        return None;
+    }
+    let digits = digits.replace("_","");
+    if !digits.chars().all(|c| c.is_digit(radix)){
+        return None;
+    }
+
+    i64::from_str_radix(&format!("{}{}",sign,digits),radix).ok()
+}
+
 impl Lexer{
-    pub fn new(source:String)->Lexer{
+    pub fn new(source:String)->Result<Lexer,String>{
+        Lexer::build(source,false)
+    }
+
+    // the lexer for tools like the formatter, which emits comments as tokens
+    pub fn with_comments(source:String)->Result<Lexer,String>{
+        Lexer::build(source,true)
+    }
+
+    fn build(source:String,keep_comments:bool)->Result<Lexer,String>{
         let mut lexer = Lexer{
-            source : source,
+            source : source.chars().collect(),
             // 次のトークンの最初の文字のインデックス
             index : 0 ,
             tokens : vec![],
             token_index : 0,
+            keep_comments,
         };
 
-        lexer.read_all_tokens();
-
-        lexer
+        match lexer.read_all_tokens(){
+            Ok(_) => Ok(lexer),
+            Err(e) => Err(e),
+        }
     }
 
     pub fn is_end(&self)->bool{
@@ -59,15 +105,20 @@ impl Lexer{
         ret
     }
 
+    // the source code from start to end
+    pub fn text(&self,start:usize,end:usize)->String{
+        self.source[start..end].iter().collect()
+    }
+
     fn next_char(&self) -> Option<char> {
-        self.source.chars().nth(self.index+1)
+        self.source.get(self.index+1).cloned()
     }
 
     fn current_char(&self) -> Option<char> {
-        self.source.chars().nth(self.index)
+        self.source.get(self.index).cloned()
     }
 
-    fn skip_while(&mut self,f : &Fn(char) -> bool){
+    fn skip_while(&mut self,f : &dyn Fn(char) -> bool){
         while let Some(c) = self.current_char(){
             if ! f(c) {
                 break;
@@ -84,80 +135,245 @@ impl Lexer{
 }
 
 impl Lexer{
-    fn read_next_token(&mut self)->Option<Token>{
+    fn read_next_token(&mut self)->Option<Result<Token,String>>{
         self.skip_whitespace();
         
         if let Some(c) = self.current_char(){
             match c{
                 c if is_symbol_char(c)
-                    => Some(self.read_symbol()),
-                c if c.is_numeric() 
+                    => Some(Ok(self.read_symbol())),
+                c if self.is_number_start(c)
                     => Some(self.read_integer()),
+                ';' if self.keep_comments
+                    => Some(Ok(self.read_comment())),
                 ';' => {
                     self.skip_comment();
                     self.read_next_token()
                 },
                 '\"'=> Some(self.read_string()),
-                 _  => Some(self.read_identifier()),
+                '\\'=> Some(self.read_char()),
+                '#' if self.next_char() == Some('\"')
+                    => Some(self.read_regex()),
+                 _  => Some(Ok(self.read_identifier())),
             }
         }else{
             None
         }
     }
 
-    fn read_all_tokens(&mut self){
+    fn read_all_tokens(&mut self)->Result<(),String>{
+        // #!/usr/bin/env simple_lisp
+        if self.current_char() == Some('#') && self.next_char() == Some('!'){
+            let token = self.read_comment();
+            if self.keep_comments{
+                self.tokens.push(token);
+            }
+        }
+
         while let Some(token) = self.read_next_token(){
-            self.tokens.push(token);
+            match token{
+                Ok(token) => self.tokens.push(token),
+                Err(e) => return Err(e),
+            }
         }
+
+        Ok(())
     }
 
     fn skip_comment(&mut self){
         self.skip_while(&|c| c != '\n');
     }
 
-    fn read_string(&mut self) -> Token{
+    fn read_comment(&mut self) -> Token{
+        let start = self.index;
+        self.skip_comment();
+
+        Token{
+            kind : TokenKind::Comment(self.text(start,self.index)),
+            start : start,
+            end : self.index,
+        }
+    }
+
+    fn read_string(&mut self) -> Result<Token,String>{
         let start = self.index;
         let mut result = String::new();
-        let mut backslash = false;
         let mut end = false;
 
         self.index += 1;
         while let Some(c) = self.current_char(){
-            if backslash{
-                result.push(
-                    match c {
-                        'n' => '\n',
-                        'r' => '\r',
-                        't' => '\t',
-                        c => c,
-                    }
-                );
-                backslash=false;
-            }else if c == '\\'{
-                backslash = true;
+            self.index += 1;
+
+            if c == '\\'{
+                match self.read_escape(){
+                    Ok(c) => result.push(c),
+                    Err(e) => return Err(e),
+                }
             }else if c == '\"'{
                 end = true;
+                break;
             }else{
                 result.push(c);
             }
+        }
+
+        if !end{
+            return Err(format!("Unexpected end of code: found EOF while reading string literal"));
+        }
+
+        Ok(Token{
+            kind : TokenKind::Str(result),
+            start : start,
+            end : self.index,
+        })
+    }
 
+    fn read_regex(&mut self) -> Result<Token,String>{
+        // #"\d+" : backslashes are kept for the regex engine
+        let start = self.index;
+        let mut result = String::new();
+        let mut end = false;
+
+        self.index += 2;
+        while let Some(c) = self.current_char(){
             self.index += 1;
-            if end {
+
+            if c == '\\'{
+                match self.current_char(){
+                    Some('\"') => result.push('\"'),
+                    Some(c) => {
+                        result.push('\\');
+                        result.push(c);
+                    },
+                    None => break,
+                }
+                self.index += 1;
+            }else if c == '\"'{
+                end = true;
                 break;
+            }else{
+                result.push(c);
             }
         }
 
         if !end{
-            panic!("Unexpedted end of code: found EOF while reading literal");
+            return Err(format!("Unexpected end of code: found EOF while reading regex literal"));
         }
 
-        Token{
-            kind : TokenKind::Str(result),
+        Ok(Token{
+            kind : TokenKind::Regex(result),
             start : start,
             end : self.index,
+        })
+    }
+
+    // reads the escape sequence after a backslash in a string literal
+    fn read_escape(&mut self) -> Result<char,String>{
+        let c = match self.current_char(){
+            Some(c) => c,
+            None => return Err(format!(
+                "Unexpected end of code: found EOF while reading string literal")),
+        };
+        self.index += 1;
+
+        match c{
+            'n' => Ok('\n'),
+            'r' => Ok('\r'),
+            't' => Ok('\t'),
+            '0' => Ok('\0'),
+            '\\' => Ok('\\'),
+            '\"' => Ok('\"'),
+            'u' => {
+                // \u00e9 or \u{1F600}
+                let braced = self.current_char() == Some('{');
+                if braced{
+                    self.index += 1;
+                }
+
+                let start = self.index;
+                if braced{
+                    self.skip_while(&|c| c != '}' && c != '\"');
+                }else{
+                    self.index = (self.index+4).min(self.source.len());
+                }
+                let hex : String = self.source[start..self.index].iter().collect();
+
+                if braced{
+                    if self.current_char() != Some('}'){
+                        return Err(format!("Unclosed unicode escape: \\u{{{}",hex));
+                    }
+                    self.index += 1;
+                }
+
+                let valid_length = if braced{
+                    hex.len() >= 1 && hex.len() <= 6
+                }else{
+                    hex.len() == 4
+                };
+                let code = if valid_length{
+                    u32::from_str_radix(&hex,16).ok()
+                }else{
+                    None
+                };
+
+                match code.and_then(::std::char::from_u32){
+                    Some(c) => Ok(c),
+                    // the escape is shown as written
+                    None if braced => Err(format!("Invalid unicode escape: \\u{{{}}}",hex)),
+                    None => Err(format!("Invalid unicode escape: \\u{}",hex)),
+                }
+            },
+            c => Err(format!("Unknown escape sequence: \\{}",c)),
         }
     }
 
+    fn read_char(&mut self)->Result<Token,String>{
+        // \a, \newline, \u00e9
+        let start = self.index;
+
+        self.index += 1;
+        let first = match self.current_char(){
+            Some(c) => c,
+            None => return Err(format!(
+                "Unexpected end of code: found EOF while reading character literal")),
+        };
+        self.index += 1;
+        if first.is_alphanumeric(){
+            self.skip_while(&|c| c.is_alphanumeric());
+        }
+        let name : String = self.source[start+1..self.index].iter().collect();
+
+        let c = if name.chars().count() == 1{
+            first
+        }else{
+            match name.as_str(){
+                "newline" => '\n',
+                "space" => ' ',
+                "tab" => '\t',
+                "return" => '\r',
+                "backspace" => '\u{8}',
+                "formfeed" => '\u{c}',
+                _ => {
+                    let code = if name.starts_with('u') && name.len() == 5{
+                        u32::from_str_radix(&name[1..],16).ok()
+                    }else{
+                        None
+                    };
+                    match code.and_then(::std::char::from_u32){
+                        Some(c) => c,
+                        None => return Err(format!("Unsupported character: \\{}",name)),
+                    }
+                },
+            }
+        };
+
+        Ok(Token{
+            kind : TokenKind::Char(c),
+            start : start,
+            end : self.index,
+        })
+    }
+
     fn read_identifier(&mut self)->Token{
         let start = self.index;
         self.skip_while(&|c| 
@@ -166,7 +382,7 @@ impl Lexer{
             && !c.is_whitespace()
             && c != ','
             && c != ';');
-        let token_str = self.source[start..self.index].to_string();
+        let token_str = self.source[start..self.index].iter().collect::<String>();
 
         Token{
             kind : TokenKind::Identifier(token_str),
@@ -175,16 +391,31 @@ impl Lexer{
         }
     }
     
-    fn read_integer(&mut self)->Token{
+    fn is_number_start(&self,c:char)->bool{
+        // 12, -12, +12
+        c.is_ascii_digit()
+            || ((c == '-' || c == '+')
+                && self.next_char().is_some_and(|c| c.is_ascii_digit()))
+    }
+
+    fn read_integer(&mut self)->Result<Token,String>{
+        // -1_000, 0xFF, 0o17, 0b1010
         let start = self.index;
-        self.skip_while(&|c| c.is_numeric());
-        let token_str = self.source[start..self.index].to_string();
-        let num : i64 = token_str.parse().unwrap();
+        self.skip_while(&|c| 
+            !is_symbol_char(c)
+            && c != '\"'
+            && !c.is_whitespace()
+            && c != ','
+            && c != ';');
+        let token_str = self.source[start..self.index].iter().collect::<String>();
 
-        Token{
-            kind : TokenKind::Integer(num),
-            start : start,
-            end : self.index,
+        match parse_integer(&token_str){
+            Some(num) => Ok(Token{
+                kind : TokenKind::Integer(num),
+                start : start,
+                end : self.index,
+            }),
+            None => Err(format!("Invalid number literal: {}",token_str)),
         }
     }
 
@@ -209,7 +440,7 @@ impl Lexer{
 #[test]
 fn test_lexer_0(){
     let src = "(def! gensym (fn* [] ; This is comment \n (symbol (str \"G__\" (swap! *gensym-counter* (fn* [x] (+ 1 x)))))))".to_string();    
-    let mut lexer = Lexer::new(src);
+    let mut lexer = Lexer::new(src).unwrap();
     let token_list = vec![
         TokenKind::Symbol("(".to_string()),
         TokenKind::Identifier("def!".to_string()),
@@ -250,4 +481,50 @@ fn test_lexer_0(){
     }
 
     assert_eq!(lexer.read_next_token().is_some(),false);
-}
\ No newline at end of file
+}
+#[test]
+fn test_lexer_1(){
+    let src = "\"a\\\\b\\u00e9\\u{1F600}\\0\"".to_string();
+    let mut lexer = Lexer::new(src).unwrap();
+    assert_eq!(
+        lexer.next().unwrap().kind,
+        TokenKind::Str("a\\b\u{e9}\u{1F600}\0".to_string()));
+
+    assert!(Lexer::new("\"abc".to_string()).is_err());
+    assert!(Lexer::new("\"\\q\"".to_string()).is_err());
+    assert_eq!(Lexer::new("\"\\u{110000}\"".to_string()).err(),
+        Some("Invalid unicode escape: \\u{110000}".to_string()));
+}
+
+#[test]
+fn test_lexer_2(){
+    let src = "-5 +7 0xFF -0b1010 0o17 1_000_000 - -x -9223372036854775808".to_string();
+    let mut lexer = Lexer::new(src).unwrap();
+    let token_list = vec![
+        TokenKind::Integer(-5),
+        TokenKind::Integer(7),
+        TokenKind::Integer(255),
+        TokenKind::Integer(-10),
+        TokenKind::Integer(15),
+        TokenKind::Integer(1000000),
+        TokenKind::Identifier("-".to_string()),
+        TokenKind::Identifier("-x".to_string()),
+        TokenKind::Integer(i64::MIN),
+    ];
+
+    for kind in token_list{
+        assert_eq!(lexer.next().unwrap().kind,kind);
+    }
+
+    assert!(Lexer::new("0xZZ".to_string()).is_err());
+    assert!(Lexer::new("1__0".to_string()).is_err());
+    assert!(Lexer::new("99999999999999999999".to_string()).is_err());
+}
+
+#[test]
+fn test_lexer_3(){
+    let src = "#!/usr/bin/env simple_lisp\n(+ 1 2)".to_string();
+    let mut lexer = Lexer::new(src).unwrap();
+
+    assert_eq!(lexer.next().unwrap().kind,TokenKind::Symbol("(".to_string()));
+}
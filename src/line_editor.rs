@@ -0,0 +1,105 @@
+use rustyline::{Editor,Helper,Context,Config,EditMode};
+use rustyline::completion::Completer;
+use rustyline::hint::Hinter;
+use rustyline::highlight::Highlighter;
+use rustyline::validate::Validator;
+use rustyline::history::DefaultHistory;
+use rustyline::error::ReadlineError;
+use std::env;
+use std::path::PathBuf;
+
+const HISTORY_FILE : &str = ".simple_lisp_history";
+
+fn is_delimiter(c:char)->bool{
+    c.is_whitespace()
+    || "()[]{}'`~@\",;".contains(c)
+}
+
+pub struct SymbolCompleter{
+    names : Vec<String>,
+}
+
+impl Completer for SymbolCompleter{
+    type Candidate = String;
+
+    fn complete(&self,line:&str,pos:usize,_ctx:&Context)
+        -> rustyline::Result<(usize,Vec<String>)>{
+        let start = match line[..pos].rfind(is_delimiter){
+            Some(i) => i + line[i..].chars().next().unwrap().len_utf8(),
+            None => 0,
+        };
+        let word = &line[start..pos];
+
+        let candidates = self.names
+            .iter()
+            .filter(|name| name.starts_with(word))
+            .cloned()
+            .collect();
+
+        Ok((start,candidates))
+    }
+}
+
+impl Hinter for SymbolCompleter{
+    type Hint = String;
+}
+
+impl Highlighter for SymbolCompleter{}
+
+impl Validator for SymbolCompleter{}
+
+impl Helper for SymbolCompleter{}
+
+pub struct LineEditor{
+    editor : Editor<SymbolCompleter,DefaultHistory>,
+    history_path : Option<PathBuf>,
+}
+
+impl LineEditor{
+    pub fn new()->Result<LineEditor,String>{
+        let config = Config::builder()
+            .edit_mode(EditMode::Emacs)
+            .auto_add_history(false)
+            .build();
+        let mut editor = match Editor::with_config(config){
+            Ok(v) => v,
+            Err(e) => return Err(format!("Cannot start line editor: {}",e)),
+        };
+        editor.set_helper(Some(SymbolCompleter{ names : vec![] }));
+
+        let history_path = env::var_os("HOME")
+            .map(|home| PathBuf::from(home).join(HISTORY_FILE));
+        if let Some(ref path) = history_path{
+            // the history file does not exist at the first run
+            let _ = editor.load_history(path);
+        }
+
+        Ok(LineEditor{
+            editor,
+            history_path,
+        })
+    }
+
+    // names are the candidates of tab completion
+    pub fn read_line(&mut self,prompt:&str,mut names:Vec<String>)->Result<String,ReadlineError>{
+        names.sort();
+        names.dedup();
+        if let Some(helper) = self.editor.helper_mut(){
+            helper.names = names;
+        }
+
+        let line = match self.editor.readline(prompt){
+            Ok(v) => v,
+            Err(e) => return Err(e),
+        };
+
+        if line.trim() != ""{
+            let _ = self.editor.add_history_entry(line.as_str());
+            if let Some(ref path) = self.history_path{
+                let _ = self.editor.save_history(path);
+            }
+        }
+
+        Ok(format!("{}\n",line))
+    }
+}
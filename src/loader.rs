@@ -0,0 +1,227 @@
+use interpreter::Interpreter;
+use types::MalType;
+use std::env;
+use std::fs::File;
+use std::io::BufReader;
+use std::io::prelude::*;
+use std::path::{Path,PathBuf};
+
+// the directories in this variable are searched before "."
+pub const LOAD_PATH_VAR : &str = "SIMPLE_LISP_PATH";
+
+// the initial value of *load-path*
+pub fn default_load_path()->MalType{
+    let mut dirs = vec![];
+
+    if let Some(paths) = env::var_os(LOAD_PATH_VAR){
+        for dir in env::split_paths(&paths){
+            dirs.push(MalType::Str(dir.to_string_lossy().to_string()));
+        }
+    }
+    dirs.push(MalType::Str(".".to_string()));
+
+    MalType::Vector(dirs)
+}
+
+impl Interpreter{
+    // the directories of *load-path*
+    fn load_path(&self)->Vec<PathBuf>{
+        let dirs = match self.env.get(&"*load-path*".to_string()){
+            Some(MalType::List(v)) | Some(MalType::Vector(v)) => v,
+            _ => vec![],
+        };
+
+        dirs.into_iter()
+            .filter_map(|dir| match dir{
+                MalType::Str(s) => Some(PathBuf::from(s)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // load-file looks for a relative path next to the file being loaded
+    // (or in the current directory at the top level), then in *load-path*.
+    fn resolve_file(&self,filename:&str)->Result<PathBuf,String>{
+        let path = Path::new(filename);
+        if path.is_absolute(){
+            return Ok(path.to_path_buf());
+        }
+
+        let base = match self.loading.last().and_then(|f| f.parent()){
+            Some(dir) => dir.join(path),
+            None => path.to_path_buf(),
+        };
+        if base.is_file(){
+            return Ok(base);
+        }
+
+        self.find_in_load_path(filename)
+    }
+
+    // require looks only in *load-path*, so that a file next to the loading one
+    // cannot shadow a library.
+    fn find_in_load_path(&self,filename:&str)->Result<PathBuf,String>{
+        for dir in self.load_path(){
+            let candidate = dir.join(filename);
+            if candidate.is_file(){
+                return Ok(candidate);
+            }
+        }
+
+        Err(format!("Cannot find file {} in *load-path*.",filename))
+    }
+
+    // Evaluates the file every time, like (load-file path).
+    pub fn load_file(&mut self,filename:String)->Result<MalType,String>{
+        let path = match self.resolve_file(&filename){
+            Ok(v) => v,
+            Err(e) => return Err(e),
+        };
+        self.eval_file(path,&filename,false,false)
+    }
+
+    // load_file for a script or :load, which is not called from an evaluation,
+    // so that the lazy sequences can be freed after each form
+    pub fn run_file(&mut self,filename:String)->Result<MalType,String>{
+        let path = match self.resolve_file(&filename){
+            Ok(v) => v,
+            Err(e) => return Err(e),
+        };
+        self.eval_file(path,&filename,false,true)
+    }
+
+    // Evaluates the file of a library unless it is already loaded.
+    pub fn load_library(&mut self,filename:&str)->Result<MalType,String>{
+        let path = match self.find_in_load_path(filename){
+            Ok(v) => v,
+            Err(e) => return Err(e),
+        };
+        self.eval_file(path,filename,true,false)
+    }
+
+    // Evaluates the file, and goes back to the namespace before the file.
+    fn eval_file(&mut self,path:PathBuf,filename:&str,once:bool,top_level:bool)->Result<MalType,String>{
+        let path = match path.canonicalize(){
+            Ok(v) => v,
+            Err(_) => return Err(format!("Cannot open file {}.",filename)),
+        };
+
+        if self.loading.contains(&path){
+            let mut chain : Vec<String> = self.loading
+                .iter()
+                .skip_while(|f| **f != path)
+                .map(|f| f.display().to_string())
+                .collect();
+            chain.push(path.display().to_string());
+            return Err(format!("Cyclic load: {}",chain.join(" -> ")));
+        }
+        if once && self.loaded.contains(&path){
+            return Ok(MalType::Nil);
+        }
+        if !once && self.loading.is_empty(){
+            self.loaded_file = Some(path.display().to_string());
+        }
+
+        let file = match File::open(&path){
+            Ok(v) => v,
+            Err(_) => return Err(format!("Cannot open file {}.",filename)),
+        };
+        let mut buf_reader = BufReader::new(file);
+        let mut code = String::new();
+        match buf_reader.read_to_string(&mut code){
+            Ok(_) => {},
+            Err(_) => return Err(format!("Cannot read file {}.",filename)),
+        }
+
+        let ns = self.current_ns();
+        self.loading.push(path.clone());
+        let result = if top_level{
+            self.rep_program(code)
+        }else{
+            self.eval_source(code,filename)
+        };
+        self.loading.pop();
+        self.env.set_root("*ns*".to_string(),MalType::Str(ns));
+
+        if result.is_ok(){
+            self.loaded.insert(path);
+        }
+
+        result
+    }
+
+    // The forms are evaluated like the body of do, so that an error or
+    // a thrown value reaches the caller of load-file or require as it is.
+    fn eval_source(&mut self,code:String,filename:&str)->Result<MalType,String>{
+        let asts = match self.read(code){
+            Ok(v) => v,
+            Err(e) => return Err(format!("Cannot parse file {}: {}",filename,e)),
+        };
+
+        let mut last = MalType::Nil;
+        for ast in asts{
+            last = match self.eval(ast){
+                Ok(v) => v,
+                Err(e) => return Err(e),
+            };
+        }
+
+        Ok(last)
+    }
+
+    // (load-file "a.mal")
+    pub fn mal_load_file(&mut self,xs:Vec<MalType>)->Result<MalType,String>{
+        if xs.len() != 1{
+            return Err(format!(
+                "The function load-file needs exactly 1 arguments, we got {}.",xs.len()));
+        }
+
+        match xs[0]{
+            MalType::Str(ref filename) => self.load_file(filename.clone()),
+            _ => Err(format!(
+                "The argument of load-file must be string, we got {}.",
+                xs[0].to_string(true))),
+        }
+    }
+}
+
+#[test]
+fn test_loader_0(){
+    let dir = env::temp_dir().join(format!("simple_lisp_loader_{}",std::process::id()));
+    std::fs::create_dir_all(dir.join("sub/my")).unwrap();
+    std::fs::create_dir_all(dir.join("lib/my")).unwrap();
+    std::fs::write(dir.join("main.mal"),"(load-file \"sub/a.mal\") (load-file \"sub/a.mal\")").unwrap();
+    std::fs::write(dir.join("sub/a.mal"),"(swap! counter inc) (load-file \"b.mal\")").unwrap();
+    std::fs::write(dir.join("sub/b.mal"),"(load-file \"a.mal\")").unwrap();
+
+    let mut lisp = Interpreter::new();
+    lisp.load_prelude().unwrap();
+    lisp.rep("(def! counter (atom 0))".to_string()).unwrap();
+
+    let main = dir.join("main.mal").to_string_lossy().to_string();
+    let result = lisp.load_file(main.clone());
+    assert!(result.unwrap_err().contains("Cyclic load"));
+
+    // load-file always evaluates, and :reload loads the top-level file
+    std::fs::write(dir.join("sub/b.mal"),"(require 'my.util)").unwrap();
+    std::fs::write(dir.join("sub/my/util.mal"),"(ns my.util) (def! x :shadow)").unwrap();
+    std::fs::write(dir.join("lib/my/util.mal"),"(ns my.util) (def! x :lib) (swap! counter inc)").unwrap();
+    let code = format!("(def! *load-path* [\"{}\"]) (reset! counter 0)",
+        dir.join("lib").to_string_lossy().replace('\\',"/"));
+    lisp.rep(code).unwrap();
+    lisp.load_file(main).unwrap();
+
+    // a/b/a/b loads my.util once from *load-path*
+    assert_eq!(lisp.rep("@counter".to_string()),Ok(MalType::Integer(3)));
+    assert_eq!(lisp.rep("my.util/x".to_string()),Ok(MalType::Keyword(":lib".to_string())));
+    let expected = dir.join("main.mal").canonicalize().unwrap().display().to_string();
+    assert_eq!(lisp.loaded_file,Some(expected));
+
+    // a value thrown in the file reaches the caller as it is
+    std::fs::write(dir.join("bad.mal"),"(throw (ex-info \"bad\" {:n 1}))").unwrap();
+    let code = format!("(try* (load-file \"{}\") (catch* :ex-info e (ex-data e)))",
+        dir.join("bad.mal").to_string_lossy().replace('\\',"/"));
+    assert_eq!(lisp.rep(code).unwrap().to_string(true),"{:n 1}");
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
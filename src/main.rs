@@ -1,3 +1,6 @@
+extern crate rustyline;
+extern crate ctrlc;
+
 
 pub mod interpreter;
 pub mod reader;
@@ -7,16 +10,219 @@ pub mod types;
 pub mod lexer;
 pub mod core;
 pub mod env;
+pub mod lazy;
+pub mod strings;
+pub mod regex;
+pub mod line_editor;
+pub mod commands;
+pub mod formatter;
+pub mod cst;
+pub mod namespace;
+pub mod loader;
+pub mod exception;
+pub mod file;
+
+use interpreter::{Interpreter,Outcome,INTERRUPTED};
+use std::sync::atomic::Ordering;
+use std::process::exit;
+use formatter::format_source;
+use std::io::{self,IsTerminal,Read};
+use std::fs;
+
+const USAGE : &str = "\
+Usage: simple_lisp [options] [script [args...]]
+       simple_lisp fmt [--check] [files...]
+
+The script `-` reads the program from standard input.
+
+Options:
+    -e EXPR         evaluate EXPR and print the result
+    -i              start the REPL after running the script or EXPR
+    --no-prelude    do not load lib.mal
+    -h, --help      print this message
 
-use interpreter::Interpreter;
+fmt formats the files in place, or standard input to standard output.
+With --check it only lists the unformatted files and fails if there are any.";
+
+struct Options{
+    exprs : Vec<String>,
+    script : Option<String>,
+    argv : Vec<String>,
+    interactive : bool,
+    prelude : bool,
+}
+
+fn parse_args(args:Vec<String>)->Result<Options,String>{
+    let mut options = Options{
+        exprs : vec![],
+        script : None,
+        argv : vec![],
+        interactive : false,
+        prelude : true,
+    };
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next(){
+        match arg.as_str(){
+            "-e" => match args.next(){
+                Some(expr) => options.exprs.push(expr),
+                None => return Err(format!("-e needs an expression.")),
+            },
+            "-i" => options.interactive = true,
+            "--no-prelude" => options.prelude = false,
+            "-h" | "--help" => {
+                println!("{}",USAGE);
+                exit(0);
+            },
+            // the arguments after the script are passed to it
+            _ if !arg.starts_with('-') || arg == "-" => {
+                options.script = Some(arg);
+                options.argv = args.collect();
+                break;
+            },
+            _ => return Err(format!("Unknown option: {}",arg)),
+        }
+    }
+
+    Ok(options)
+}
+
+// runs the at-exit hooks and exits the process
+fn finish(lisp:&mut Interpreter,outcome:Outcome)->!{
+    let code = match outcome{
+        Outcome::Value(_) => 0,
+        Outcome::Error(e) => {
+            eprintln!("{}",e);
+            1
+        },
+        Outcome::Exit(code) => code,
+    };
+
+    lisp.run_exit_hooks();
+    exit(code)
+}
+
+fn run_fmt(args:Vec<String>)->i32{
+    let check = args.iter().any(|arg| arg == "--check");
+    let files : Vec<String> = args.into_iter().filter(|arg| arg != "--check").collect();
+    let mut status = 0;
+
+    if files.is_empty(){
+        let mut code = String::new();
+        if io::stdin().read_to_string(&mut code).is_err(){
+            eprintln!("Cannot read standard input.");
+            return 1;
+        }
+        return match format_source(&code){
+            Ok(formatted) if check => if formatted == code{ 0 }else{ 1 },
+            Ok(formatted) => {
+                print!("{}",formatted);
+                0
+            },
+            Err(e) => {
+                eprintln!("Parse error: {}",e);
+                1
+            },
+        };
+    }
+
+    for file in files{
+        let code = match fs::read_to_string(&file){
+            Ok(v) => v,
+            Err(_) => {
+                eprintln!("Cannot read file {}.",file);
+                status = 1;
+                continue;
+            },
+        };
+        let formatted = match format_source(&code){
+            Ok(v) => v,
+            Err(e) => {
+                eprintln!("Parse error in {}: {}",file,e);
+                status = 1;
+                continue;
+            },
+        };
+
+        if formatted == code{
+            continue;
+        }
+        if check{
+            println!("{}",file);
+            status = 1;
+        }else if fs::write(&file,formatted).is_err(){
+            eprintln!("Cannot write file {}.",file);
+            status = 1;
+        }
+    }
+
+    status
+}
 
 fn main() {
+    let args : Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(|arg| arg.as_str()) == Some("fmt"){
+        exit(run_fmt(args.into_iter().skip(1).collect()));
+    }
+
+    let options = match parse_args(args){
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("{}\n\n{}",e,USAGE);
+            exit(2);
+        },
+    };
     let mut lisp = Interpreter::new();
 
-    match lisp.load_file("lib.mal".to_string()){
-        Ok(_) => {},
-        Err(e) => println!("Setup error: {}",e) ,
+    if options.prelude{
+        if let Err(e) = lisp.load_prelude(){
+            eprintln!("Setup error: {}",e);
+            exit(1);
+        }
     }
+    lisp.set_argv(options.argv);
 
-    lisp.repl_loop();
+    // piped code without a script is run as a program, not line by line
+    let script = match options.script{
+        None if options.exprs.is_empty() && !options.interactive && !io::stdin().is_terminal()
+            => Some("-".to_string()),
+        script => script,
+    };
+
+    // Ctrl-C stops the evaluation instead of the REPL.
+    // The line editor replaces the SIGINT handler, so the handler is set after it.
+    if options.interactive || (script.is_none() && options.exprs.is_empty()){
+        lisp.start_line_editor();
+        if let Err(e) = ctrlc::set_handler(|| INTERRUPTED.store(true,Ordering::SeqCst)){
+            eprintln!("Cannot set Ctrl-C handler: {}",e);
+        }
+    }
+
+    for expr in options.exprs.iter(){
+        match lisp.run(expr.clone()){
+            Outcome::Value(v) => lisp.print(Ok(v)),
+            outcome => finish(&mut lisp,outcome),
+        }
+    }
+
+    if let Some(script) = script{
+        let result = if script == "-"{
+            lisp.load_stdin()
+        }else{
+            lisp.run_file(script)
+        };
+        match lisp.to_outcome(result){
+            Outcome::Value(_) => {},
+            outcome => finish(&mut lisp,outcome),
+        }
+    }else if options.exprs.is_empty(){
+        let code = lisp.repl_loop();
+        finish(&mut lisp,Outcome::Exit(code));
+    }
+
+    if options.interactive{
+        let code = lisp.repl_loop();
+        finish(&mut lisp,Outcome::Exit(code));
+    }
+    finish(&mut lisp,Outcome::Exit(0));
 }
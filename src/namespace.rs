@@ -0,0 +1,185 @@
+use interpreter::Interpreter;
+use types::MalType;
+use std::collections::{HashMap,HashSet};
+
+// The definitions of the namespace "user" are in the root of Env
+// together with the built-in functions and lib.mal.
+pub const USER_NS : &str = "user";
+
+#[derive(Default)]
+pub struct Namespace{
+    pub defs : HashMap<String,MalType>,
+    pub privates : HashSet<String>,
+    // alias -> namespace name, from (require '[my.lib :as l])
+    pub aliases : HashMap<String,String>,
+}
+
+// "my.lib/helper" -> ("my.lib","helper")
+fn split_qualified(ident:&str)->Option<(&str,&str)>{
+    match ident.find('/'){
+        Some(i) if i > 0 && i < ident.len()-1 => Some((&ident[..i],&ident[i+1..])),
+        _ => None,
+    }
+}
+
+impl Interpreter{
+    // the namespace of the code being evaluated.
+    // Functions keep the namespace they are defined in as *ns* in their local_env.
+    pub fn current_ns(&self)->String{
+        match self.env.get(&"*ns*".to_string()){
+            Some(MalType::Str(s)) => s,
+            _ => USER_NS.to_string(),
+        }
+    }
+
+    // local variables, then the current namespace, then the root
+    pub fn resolve_identifier(&self,ident:&str)->Result<MalType,String>{
+        let ns = self.current_ns();
+
+        if let Some((prefix,name)) = split_qualified(ident){
+            let target = match self.namespaces.get(&ns).and_then(|n| n.aliases.get(prefix)){
+                Some(v) => v.clone(),
+                None => prefix.to_string(),
+            };
+            return self.resolve_qualified(&target,name,target == ns)
+                .ok_or(format!("Unknown symbol: {}",ident))
+                .and_then(|v| v);
+        }
+
+        if let Some(v) = self.env.get_local(&ident.to_string()){
+            return Ok(v);
+        }
+        if ns != USER_NS{
+            if let Some(v) = self.namespaces.get(&ns).and_then(|n| n.defs.get(ident)){
+                return Ok(v.clone());
+            }
+        }
+        match self.env.get(&ident.to_string()){
+            Some(v) => Ok(v),
+            None => Err(format!("Unknown symbol: {}",ident)),
+        }
+    }
+
+    fn resolve_qualified(&self,ns:&str,name:&str,inside:bool)->Option<Result<MalType,String>>{
+        if ns == USER_NS{
+            return self.env.get(&name.to_string()).map(Ok);
+        }
+
+        let namespace = match self.namespaces.get(ns){
+            Some(v) => v,
+            None => return None,
+        };
+        match namespace.defs.get(name){
+            Some(_) if !inside && namespace.privates.contains(name) =>
+                Some(Err(format!("{}/{} is private.",ns,name))),
+            Some(v) => Some(Ok(v.clone())),
+            None => None,
+        }
+    }
+
+    // def! and def-! at the top level
+    pub fn define(&mut self,name:String,val:MalType,private:bool){
+        let ns = self.current_ns();
+
+        if ns == USER_NS || self.env.get_level() > 1{
+            self.env.set(name,val);
+            return;
+        }
+
+        let namespace = self.namespaces.entry(ns).or_default();
+        if private{
+            namespace.privates.insert(name.clone());
+        }else{
+            namespace.privates.remove(&name);
+        }
+        namespace.defs.insert(name,val);
+    }
+
+    // (ns my.lib)
+    pub fn mal_ns(&mut self,xs:Vec<MalType>)->Result<MalType,String>{
+        if xs.len() != 1{
+            return Err(format!(
+                "The function ns needs exactly 1 arguments, we got {}.",xs.len()));
+        }
+        let name = match xs[0]{
+            MalType::Identifier(ref s) if !s.contains('/') => s.clone(),
+            _ => return Err(format!(
+                "The argument of ns must be symbol, we got {}.",xs[0].to_string(true))),
+        };
+
+        self.namespaces.entry(name.clone()).or_default();
+        self.env.set_root("*ns*".to_string(),MalType::Str(name));
+        Ok(MalType::Nil)
+    }
+
+    // (require 'my.lib) or (require '[my.lib :as l])
+    pub fn mal_require(&mut self,xs:Vec<MalType>)->Result<MalType,String>{
+        for x in xs{
+            let (name,alias) = match x{
+                MalType::Identifier(s) => (s,None),
+                MalType::Vector(ref v) if v.len() == 1 || v.len() == 3 => {
+                    let name = match v[0]{
+                        MalType::Identifier(ref s) => s.clone(),
+                        _ => return Err(format!(
+                            "The namespace name must be symbol, we got {}.",v[0].to_string(true))),
+                    };
+                    let alias = match v.get(1){
+                        None => None,
+                        Some(MalType::Keyword(k)) if k == ":as" => match v[2]{
+                            MalType::Identifier(ref s) => Some(s.clone()),
+                            _ => return Err(format!(
+                                "The alias must be symbol, we got {}.",v[2].to_string(true))),
+                        },
+                        Some(y) => return Err(format!(
+                            "Unknown option of require: {}",y.to_string(true))),
+                    };
+                    (name,alias)
+                },
+                _ => return Err(format!(
+                    "The argument of require must be symbol or [name :as alias], we got {}.",
+                    x.to_string(true))),
+            };
+
+            if name != USER_NS && !self.namespaces.contains_key(&name){
+                if let Err(e) = self.load_namespace(&name){
+                    return Err(e);
+                }
+            }
+
+            if let Some(alias) = alias{
+                let ns = self.current_ns();
+                self.namespaces.entry(ns).or_default().aliases.insert(alias,name);
+            }
+        }
+
+        Ok(MalType::Nil)
+    }
+
+    // my.lib -> my/lib.mal in a directory of *load-path*
+    fn load_namespace(&mut self,name:&str)->Result<(),String>{
+        let relative = format!("{}.mal",name.replace('.',"/"));
+
+        if let Err(e) = self.load_library(&relative){
+            return Err(e);
+        }
+        if self.namespaces.contains_key(name){
+            Ok(())
+        }else{
+            Err(format!("{} does not define the namespace {}.",relative,name))
+        }
+    }
+}
+
+#[test]
+fn test_namespace_0(){
+    let mut lisp = Interpreter::new();
+
+    lisp.rep("(ns a) (def-! helper 1) (def! f (fn* [] helper))".to_string()).unwrap();
+    lisp.rep("(ns b) (def! helper 2)".to_string()).unwrap();
+    lisp.rep("(ns user)".to_string()).unwrap();
+
+    assert_eq!(lisp.rep("(a/f)".to_string()),Ok(MalType::Integer(1)));
+    assert_eq!(lisp.rep("b/helper".to_string()),Ok(MalType::Integer(2)));
+    assert!(lisp.rep("a/helper".to_string()).is_err());
+    assert!(lisp.rep("helper".to_string()).is_err());
+}
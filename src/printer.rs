@@ -1,11 +1,161 @@
 use interpreter::Interpreter;
 use types::MalType;
 
+const DEFAULT_PRINT_WIDTH : usize = 80;
+
 impl Interpreter{
-    pub fn print(&self,mt:Result<MalType,String>){
+    pub fn print(&mut self,mt:Result<MalType,String>){
+        let mt = match mt {
+            Ok(v) => self.realize_all(v),
+            Err(e) => Err(e),
+        };
+
         match mt {
-            Ok(v) => println!("{}",v.to_string(true)),
-            Err(e) => println!("{}",e),
+            // large results are laid out on multiple lines
+            Ok(v) => println!("{}",pretty_string(&v,self.print_width())),
+            Err(e) => {
+                // the error is reported here, so it does not exit later
+                self.exit_code = None;
+                println!("{}",e);
+            },
+        }
+    }
+
+    // the value of *print-width*
+    pub fn print_width(&self)->usize{
+        match self.env.get(&"*print-width*".to_string()){
+            Some(MalType::Integer(n)) if n > 0 => n as usize,
+            _ => DEFAULT_PRINT_WIDTH,
+        }
+    }
+
+    pub fn mal_pprint(&mut self,xs:Vec<MalType>)->Result<MalType,String>{
+        if xs.len() != 1 && xs.len() != 2{
+            return Err(format!(
+                "The function pprint needs 1 or 2 arguments, we got {}.",xs.len()));
+        }
+
+        let width = match xs.get(1){
+            None => self.print_width(),
+            Some(MalType::Integer(n)) if *n > 0 => *n as usize,
+            Some(x) => return Err(format!(
+                "The second argument of pprint must be positive integer, we got {}.",
+                x.to_string(true))),
+        };
+        let x = match self.realize_all(xs[0].clone()){
+            Ok(v) => v,
+            Err(e) => return Err(e),
+        };
+
+        println!("{}",pretty_string(&x,width));
+        Ok(MalType::Nil)
+    }
+}
+
+// Prints x readably in lines of the width if possible.
+// Lists starting with a symbol align their arguments after the symbol,
+// other sequences and dicts align their elements after the bracket.
+pub fn pretty_string(x:&MalType,width:usize)->String{
+    pretty(x,0,width)
+}
+
+fn pretty(x:&MalType,column:usize,width:usize)->String{
+    let flat = x.to_string(true);
+    if column + flat.chars().count() <= width{
+        return flat;
+    }
+
+    match x{
+        MalType::List(xs) if xs.len() >= 2 && is_head(&xs[0]) => {
+            let head = xs[0].to_string(true);
+            let column = column + 1 + head.chars().count() + 1;
+            format!("({} {})",head,pretty_items(&xs[1..],column,width))
+        },
+        MalType::List(xs) => {
+            format!("({})",pretty_items(xs,column+1,width))
+        },
+        MalType::Vector(xs) => {
+            format!("[{}]",pretty_items(xs,column+1,width))
+        },
+        MalType::Dict(hm) => {
+            let mut pairs = vec![];
+            for (key,val) in hm{
+                let mut key = key.clone();
+                let key = if key.starts_with(' '){
+                    key.remove(0);
+                    MalType::Str(key)
+                }else{
+                    MalType::Keyword(key)
+                };
+                pairs.push((key.to_string(true),val));
+            }
+            pairs.sort_by(|a,b| a.0.cmp(&b.0));
+
+            let column = column + 1;
+            let lines : Vec<String> = pairs
+                .iter()
+                .map(|(key,val)| format!("{} {}",
+                    key,
+                    pretty(val,column+key.chars().count()+1,width)))
+                .collect();
+
+            format!("{{{}}}",lines.join(&format!(",\n{}"," ".repeat(column))))
+        },
+        _ => flat,
+    }
+}
+
+fn pretty_items(xs:&[MalType],column:usize,width:usize)->String{
+    // atoms such as numbers are filled into lines instead of one per line
+    if xs.iter().all(|x| !is_collection(x)){
+        let mut lines = vec![];
+        let mut line = String::new();
+
+        for x in xs{
+            let s = x.to_string(true);
+            if !line.is_empty() && column + line.chars().count() + 1 + s.chars().count() > width{
+                lines.push(line);
+                line = String::new();
+            }
+            if !line.is_empty(){
+                line.push(' ');
+            }
+            line.push_str(&s);
         }
+        lines.push(line);
+
+        return lines.join(&format!("\n{}"," ".repeat(column)));
     }
+
+    let lines : Vec<String> = xs
+        .iter()
+        .map(|x| pretty(x,column,width))
+        .collect();
+
+    lines.join(&format!("\n{}"," ".repeat(column)))
+}
+
+fn is_collection(x:&MalType)->bool{
+    matches!(x,MalType::List(_) | MalType::Vector(_) | MalType::Dict(_))
+}
+
+fn is_head(x:&MalType)->bool{
+    matches!(x,MalType::Identifier(_) | MalType::BuiltInFunction(_))
+}
+
+#[test]
+fn test_pretty_string_0(){
+    let x = MalType::List(vec![
+        MalType::Identifier("def!".to_string()),
+        MalType::Identifier("xs".to_string()),
+        MalType::Vector(vec![
+            MalType::Integer(100),
+            MalType::Integer(200),
+            MalType::Integer(300),
+        ]),
+    ]);
+
+    assert_eq!(pretty_string(&x,80),"(def! xs [100 200 300])");
+    assert_eq!(pretty_string(&x,20),"(def! xs\n      [100 200 300])");
+    assert_eq!(pretty_string(&x,14),"(def! xs\n      [100 200\n       300])");
 }
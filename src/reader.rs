@@ -1,10 +1,98 @@
 use interpreter::Interpreter;
 use lexer::Lexer;
 use types::{TokenKind,MalType,BuiltInFunction};
+use regex::Regex;
+use core::sequence_to_pair;
+use std::collections::HashMap;
+
+// the function of the reader macro such as ' and @
+pub fn reader_macro(symbol:&str)->Option<BuiltInFunction>{
+    match symbol{
+        "'" => Some(BuiltInFunction::Quote),
+        "@" => Some(BuiltInFunction::Deref),
+        "`" => Some(BuiltInFunction::QuasiQuote),
+        "~" => Some(BuiltInFunction::UnQuote),
+        "~@" => Some(BuiltInFunction::SpliceUnQuote),
+        _ => None,
+    }
+}
+
+// The key of a dict is the text of a keyword, a string after a space,
+// or the source of another form, which is evaluated with the dict like {k 1}.
+pub fn dict_key(x:&MalType) -> String{
+    match x{
+        MalType::Keyword(k) => k.clone(),
+        MalType::Str(s) => format!(" {}",s),
+        _ => x.to_string(true),
+    }
+}
+
+pub fn is_literal_key(key:&str) -> bool{
+    key.starts_with(':') || key.starts_with(' ')
+}
+
+// {:a x} is read as a dict whose keys and values are evaluated with it
+pub fn dict_literal(xs:Vec<MalType>) -> Result<MalType,String>{
+    let pairs = match sequence_to_pair(xs){
+        Ok(v) => v,
+        Err(e) => return Err(e),
+    };
+
+    let mut hm = HashMap::new();
+    for (key,val) in pairs{
+        hm.insert(dict_key(&key),val);
+    }
+    Ok(MalType::Dict(hm))
+}
+
+// converts the token of an atom into the value
+pub fn token_to_mal(kind:TokenKind) -> Result<MalType,String>{
+    match kind {
+        TokenKind::Symbol(_) => 
+            Err(format!("")),
+        
+        TokenKind::Identifier(s) => {
+            Ok(
+                if s == "true"{
+                    MalType::Bool(true)
+                }else if s == "false" {
+                    MalType::Bool(false)
+                }else if s == "nil" {
+                    MalType::Nil
+                }else if s.chars().nth(0).unwrap() == ':'{
+                    MalType::Keyword(s)
+                }else{
+                    MalType::Identifier(s)
+                }
+            )
+        },
+
+        TokenKind::Str(s) 
+            => Ok(MalType::Str(s)),
+
+        TokenKind::Char(c) 
+            => Ok(MalType::Char(c)),
+
+        TokenKind::Regex(s) 
+            => match Regex::new(&s){
+                Ok(re) => Ok(MalType::Regex(re)),
+                Err(e) => Err(e),
+            },
+
+        TokenKind::Integer(n) 
+            => Ok(MalType::Integer(n)),
+
+        TokenKind::Comment(_)
+            => Err(format!("It's a bug! Comments must not be read as forms.")),
+    }
+}
 
 impl Interpreter{
     pub fn read(&self,code:String) -> Result<Vec<MalType>,String>{
-        let mut lexer = Lexer::new(code);
+        let mut lexer = match Lexer::new(code){
+            Ok(v) => v,
+            Err(e) => return Err(e),
+        };
         let mut asts = Vec::new();
 
         while ! lexer.is_end(){
@@ -21,6 +109,31 @@ impl Interpreter{
         Ok(asts)
     }
 
+    // true if the code is a prefix of a form, like "(+ 1" or "\"abc".
+    // The REPL keeps reading lines while this returns true.
+    pub fn is_incomplete(&self,code:String) -> bool{
+        let mut lexer = match Lexer::new(code){
+            Ok(v) => v,
+            Err(e) => return e.starts_with("Unexpected end of code"),
+        };
+        let mut depth = 0;
+        let mut waiting_form = false;
+
+        while let Some(token) = lexer.next(){
+            waiting_form = false;
+
+            if let TokenKind::Symbol(s) = token.kind{
+                match s.as_str(){
+                    "(" | "[" | "{" => depth += 1,
+                    ")" | "]" | "}" => depth -= 1,
+                    _ => waiting_form = true, // reader macros such as ' and @
+                }
+            }
+        }
+
+        depth > 0 || waiting_form
+    }
+
     fn read_form(&self,lexer : &mut Lexer) -> Result<MalType,String>{
         let otoken = lexer.peek();
 
@@ -43,24 +156,9 @@ impl Interpreter{
                 '{' => {
                     self.read_dict(lexer)
                 },
-                '\'' => {
-                    self.read_reader_macro(lexer,BuiltInFunction::Quote)
-                },
-                '@' => {
-                    self.read_reader_macro(lexer,BuiltInFunction::Deref)
-                },
-                '`' => {
-                    self.read_reader_macro(lexer,BuiltInFunction::QuasiQuote)
-                },
-                '~' => {
-                    if s.len() == 1{
-                        self.read_reader_macro(lexer,BuiltInFunction::UnQuote)
-                    }else{
-                        self.read_reader_macro(lexer,BuiltInFunction::SpliceUnQuote)
-                    }
-                },
-                _ => {
-                    Err(format!("Unexpected symbol: {} ",s))
+                _ => match reader_macro(&s){
+                    Some(func) => self.read_reader_macro(lexer,func),
+                    None => Err(format!("Unexpected symbol: {} ",s)),
                 }
             }
         }else{
@@ -75,34 +173,7 @@ impl Interpreter{
             return Err(format!("It's a bug! See read_atom."))
         }
 
-        let token = otoken.unwrap();
-
-        match token.kind {
-            TokenKind::Symbol(_) => 
-                Err(format!("")),
-            
-            TokenKind::Identifier(s) => {
-                Ok(
-                    if s == "true"{
-                        MalType::Bool(true)
-                    }else if s == "false" {
-                        MalType::Bool(false)
-                    }else if s == "nil" {
-                        MalType::Nil
-                    }else if s.chars().nth(0).unwrap() == ':'{
-                        MalType::Keyword(s)
-                    }else{
-                        MalType::Identifier(s)
-                    }
-                )
-            },
-
-            TokenKind::Str(s) 
-                => Ok(MalType::Str(s)),
-
-            TokenKind::Integer(n) 
-                => Ok(MalType::Integer(n)),
-        }
+        token_to_mal(otoken.unwrap().kind)
     }
 
     fn read_reader_macro(&self,lexer:&mut Lexer, func :BuiltInFunction) -> Result<MalType,String>{
@@ -160,19 +231,29 @@ impl Interpreter{
         }
     }
 
+    // the form of the key which dict_key made
+    pub fn dict_key_form(&self,key:&str) -> Result<MalType,String>{
+        if key.starts_with(':'){
+            return Ok(MalType::Keyword(key.to_string()));
+        }
+        if let Some(s) = key.strip_prefix(' '){
+            return Ok(MalType::Str(s.to_string()));
+        }
+
+        match self.read(key.to_string()){
+            Ok(mut v) if v.len() == 1 => Ok(v.remove(0)),
+            Ok(_) => Err(format!("Invalid key of dict: {}",key)),
+            Err(e) => Err(e),
+        }
+    }
+
     fn read_dict(&self,lexer:&mut Lexer) -> Result<MalType,String>{
         let start = TokenKind::Symbol("{".to_string());
         let end = TokenKind::Symbol("}".to_string());
 
-        let o_hm = self.read_sequence(lexer,start,end);
-
-        if let Err(s) = o_hm{
-            Err(s)
-        }else{
-            // {:a 1 :b 2} -> (hash-map :a 1 :b 2)
-            let mut hm = o_hm.unwrap();
-            hm.insert(0,MalType::BuiltInFunction(BuiltInFunction::HashMap));
-            Ok(MalType::List(hm))
+        match self.read_sequence(lexer,start,end){
+            Ok(v) => dict_literal(v),
+            Err(s) => Err(s),
         }
     }
 
@@ -197,3 +278,30 @@ impl Interpreter{
         }
     }
 }
+
+#[test]
+fn test_reader_0(){
+    let lisp = Interpreter::new();
+
+    assert!(lisp.is_incomplete("(def! f (fn* [x]".to_string()));
+    assert!(lisp.is_incomplete("(str \"abc".to_string()));
+    assert!(lisp.is_incomplete("'".to_string()));
+    assert!(!lisp.is_incomplete("(+ 1 2) ; comment (".to_string()));
+    assert!(!lisp.is_incomplete("(+ 1 2))".to_string()));
+}
+
+#[test]
+fn test_reader_1(){
+    let mut lisp = Interpreter::new();
+
+    // a dict literal is data, and its values are evaluated with the dict
+    let quoted = lisp.rep("'{:a (+ 1 2)}".to_string()).unwrap();
+    assert_eq!(quoted.to_string(true),"{:a (+ 1 2)}");
+    assert_eq!(lisp.rep("{:a (+ 1 2)}".to_string()).unwrap().to_string(true),"{:a 3}");
+
+    // other keys are evaluated too, and a quoted one is still a dict
+    assert_eq!(lisp.rep("'{k 1}".to_string()),lisp.rep("(read-string \"{k 1}\")".to_string()));
+    assert!(matches!(lisp.rep("'{k 1}".to_string()),Ok(MalType::Dict(_))));
+    assert_eq!(lisp.rep("(let* [k :a] {k 1})".to_string()).unwrap().to_string(true),"{:a 1}");
+    assert_eq!(lisp.rep("(let* [k \"a\"] `{~k ~k})".to_string()).unwrap().to_string(true),"{\"a\" \"a\"}");
+}
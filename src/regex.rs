@@ -0,0 +1,650 @@
+// A small backtracking regular expression engine.
+// Supports literals, ., character classes ([a-z], [^0-9], \d, \w, \s),
+// anchors (^, $, \b, \B), groups ((...), (?:...)), alternation (|)
+// and quantifiers (*, +, ?, {n}, {n,}, {n,m} and their lazy versions).
+// Positions are counted in characters, not in bytes.
+// The pattern is compiled to instructions, which are run with an explicit
+// stack of backtracking points so that long texts do not overflow the stack.
+
+use interpreter::INTERRUPTED;
+use std::sync::atomic::Ordering;
+
+#[derive(Debug, Clone)]
+enum Node{
+    Char(char),
+    Any,
+    Class(Vec<ClassItem>,bool),  // items, negated
+    Start,
+    End,
+    WordBoundary(bool),          // \b or \B(negated)
+    Group(Box<Node>,Option<usize>),
+    Concat(Vec<Node>),
+    Alt(Vec<Node>),
+    Repeat(Box<Node>,usize,Option<usize>,bool), // node, min, max, greedy
+}
+
+#[derive(Debug, Clone)]
+enum ClassItem{
+    Range(char,char),
+    Digit(bool),
+    Word(bool),
+    Space(bool),
+}
+
+pub type Captures = Vec<Option<(usize,usize)>>;
+
+// an instruction of the compiled pattern
+#[derive(Debug, Clone)]
+enum Inst{
+    Char(char),
+    Any,
+    Class(Vec<ClassItem>,bool),
+    Start,
+    End,
+    WordBoundary(bool),
+    Save(usize),          // stores the position in the slot
+    Split(usize,usize),   // tries the first, and the second when it fails
+    Jmp(usize),
+    Mark(usize),          // stores the position where an iteration starts
+    Progress(usize),      // fails if the iteration matched nothing
+    Match,
+}
+
+// what to do when the current path fails
+enum Backtrack{
+    Try(usize,usize),             // pc, pos
+    Restore(usize,Option<usize>), // slot, old value
+}
+
+// the number of steps between the checks of Ctrl-C
+const INTERRUPT_INTERVAL : usize = 4096;
+
+// the largest count of {n,m}, since each iteration is compiled separately
+const MAX_REPEAT : usize = 1000;
+
+#[derive(Debug, Clone)]
+pub struct Regex{
+    source : String,
+    prog : Vec<Inst>,
+    groups : usize,
+    // 2 for each group, and 1 for each loop
+    slots : usize,
+}
+
+impl PartialEq for Regex{
+    fn eq(&self,other:&Regex)->bool{
+        self.source == other.source
+    }
+}
+
+fn is_word_char(c:char)->bool{
+    c.is_alphanumeric() || c == '_'
+}
+
+impl ClassItem{
+    fn matches(&self,c:char)->bool{
+        match *self{
+            ClassItem::Range(a,b) => a <= c && c <= b,
+            ClassItem::Digit(neg) => c.is_ascii_digit() != neg,
+            ClassItem::Word(neg) => is_word_char(c) != neg,
+            ClassItem::Space(neg) => c.is_whitespace() != neg,
+        }
+    }
+}
+
+struct Parser{
+    chars : Vec<char>,
+    index : usize,
+    groups : usize,
+}
+
+impl Parser{
+    fn peek(&self)->Option<char>{
+        self.chars.get(self.index).cloned()
+    }
+
+    fn parse_alt(&mut self)->Result<Node,String>{
+        let mut alts = vec![];
+
+        loop{
+            match self.parse_concat(){
+                Ok(v) => alts.push(v),
+                Err(e) => return Err(e),
+            }
+
+            if self.peek() == Some('|'){
+                self.index += 1;
+            }else{
+                break;
+            }
+        }
+
+        if alts.len() == 1{
+            Ok(alts.pop().unwrap())
+        }else{
+            Ok(Node::Alt(alts))
+        }
+    }
+
+    fn parse_concat(&mut self)->Result<Node,String>{
+        let mut nodes = vec![];
+
+        while let Some(c) = self.peek(){
+            if c == '|' || c == ')'{
+                break;
+            }
+
+            let atom = match self.parse_atom(){
+                Ok(v) => v,
+                Err(e) => return Err(e),
+            };
+            match self.parse_quantifier(atom){
+                Ok(v) => nodes.push(v),
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(Node::Concat(nodes))
+    }
+
+    fn parse_quantifier(&mut self,atom:Node)->Result<Node,String>{
+        let (min,max) = match self.peek(){
+            Some('*') => {
+                self.index += 1;
+                (0,None)
+            },
+            Some('+') => {
+                self.index += 1;
+                (1,None)
+            },
+            Some('?') => {
+                self.index += 1;
+                (0,Some(1))
+            },
+            Some('{') => {
+                match self.parse_braces(){
+                    Some(v) => v,
+                    None => return Ok(atom),
+                }
+            },
+            _ => return Ok(atom),
+        };
+
+        if let Some(max) = max{
+            if min > max{
+                return Err(format!("Invalid repetition {{{},{}}} in regex.",min,max));
+            }
+        }
+        if min > MAX_REPEAT || max.unwrap_or(0) > MAX_REPEAT{
+            return Err(format!("The count of repetition in regex must be at most {}.",MAX_REPEAT));
+        }
+
+        let greedy = if self.peek() == Some('?'){
+            self.index += 1;
+            false
+        }else{
+            true
+        };
+
+        Ok(Node::Repeat(Box::new(atom),min,max,greedy))
+    }
+
+    // {n}, {n,}, {n,m}. Otherwise '{' is an ordinary character.
+    fn parse_braces(&mut self)->Option<(usize,Option<usize>)>{
+        let rest : String = self.chars[self.index..].iter().collect();
+        let close = match rest.find('}'){
+            Some(v) => v,
+            None => return None,
+        };
+        let inner = &rest[1..close];
+        let parse = |s:&str| s.parse::<usize>().ok();
+
+        let result = if let Some(comma) = inner.find(','){
+            let min = parse(&inner[..comma]);
+            let max = &inner[comma+1..];
+            match min{
+                Some(min) if max.len() == 0 => Some((min,None)),
+                Some(min) => parse(max).map(|max| (min,Some(max))),
+                None => None,
+            }
+        }else{
+            parse(inner).map(|n| (n,Some(n)))
+        };
+
+        if result.is_some(){
+            self.index += inner.chars().count() + 2;
+        }
+
+        result
+    }
+
+    fn parse_atom(&mut self)->Result<Node,String>{
+        let c = self.peek().unwrap();
+        self.index += 1;
+
+        match c{
+            '(' => {
+                let index = if self.chars[self.index..].starts_with(&['?',':']){
+                    self.index += 2;
+                    None
+                }else{
+                    self.groups += 1;
+                    Some(self.groups)
+                };
+
+                let inner = match self.parse_alt(){
+                    Ok(v) => v,
+                    Err(e) => return Err(e),
+                };
+
+                if self.peek() != Some(')'){
+                    return Err(format!("Missing ) in regex."));
+                }
+                self.index += 1;
+
+                Ok(Node::Group(Box::new(inner),index))
+            },
+            '[' => self.parse_class(),
+            '.' => Ok(Node::Any),
+            '^' => Ok(Node::Start),
+            '$' => Ok(Node::End),
+            '*' | '+' | '?' => Err(format!("Nothing to repeat before {} in regex.",c)),
+            '\\' => {
+                match self.parse_escape(){
+                    Ok(Err(node)) => Ok(node),
+                    Ok(Ok(item)) => Ok(Node::Class(vec![item],false)),
+                    Err(e) => Err(e),
+                }
+            },
+            c => Ok(Node::Char(c)),
+        }
+    }
+
+    // Ok(Ok(item)) for escapes usable in a class, Ok(Err(node)) for the others
+    fn parse_escape(&mut self)->Result<Result<ClassItem,Node>,String>{
+        let c = match self.peek(){
+            Some(c) => c,
+            None => return Err(format!("Trailing backslash in regex.")),
+        };
+        self.index += 1;
+
+        let literal = |c| Ok(Ok(ClassItem::Range(c,c)));
+
+        match c{
+            'd' => Ok(Ok(ClassItem::Digit(false))),
+            'D' => Ok(Ok(ClassItem::Digit(true))),
+            'w' => Ok(Ok(ClassItem::Word(false))),
+            'W' => Ok(Ok(ClassItem::Word(true))),
+            's' => Ok(Ok(ClassItem::Space(false))),
+            'S' => Ok(Ok(ClassItem::Space(true))),
+            'b' => Ok(Err(Node::WordBoundary(false))),
+            'B' => Ok(Err(Node::WordBoundary(true))),
+            'n' => literal('\n'),
+            'r' => literal('\r'),
+            't' => literal('\t'),
+            '0' => literal('\0'),
+            c if c.is_alphanumeric() => Err(format!("Unknown escape \\{} in regex.",c)),
+            c => literal(c),
+        }
+    }
+
+    fn parse_class(&mut self)->Result<Node,String>{
+        let mut items = vec![];
+        let negated = if self.peek() == Some('^'){
+            self.index += 1;
+            true
+        }else{
+            false
+        };
+        let mut first = true;
+
+        loop{
+            let c = match self.peek(){
+                Some(c) => c,
+                None => return Err(format!("Missing ] in regex.")),
+            };
+            self.index += 1;
+
+            if c == ']' && !first{
+                break;
+            }
+            first = false;
+
+            let start = if c == '\\'{
+                match self.parse_escape(){
+                    Ok(Ok(ClassItem::Range(a,_))) => a,
+                    Ok(Ok(item)) => {
+                        items.push(item);
+                        continue;
+                    },
+                    Ok(Err(_)) => return Err(format!("Invalid escape in character class.")),
+                    Err(e) => return Err(e),
+                }
+            }else{
+                c
+            };
+
+            // a-z
+            let is_range = self.peek() == Some('-')
+                && self.chars.get(self.index+1).is_some_and(|c| *c != ']');
+            if !is_range{
+                items.push(ClassItem::Range(start,start));
+                continue;
+            }
+            self.index += 1;
+
+            let mut end = self.peek().unwrap();
+            self.index += 1;
+            if end == '\\'{
+                end = match self.parse_escape(){
+                    Ok(Ok(ClassItem::Range(a,_))) => a,
+                    Err(e) => return Err(e),
+                    _ => return Err(format!("Invalid range in character class.")),
+                };
+            }
+            if start > end{
+                return Err(format!("Invalid range {}-{} in character class.",start,end));
+            }
+            items.push(ClassItem::Range(start,end));
+        }
+
+        Ok(Node::Class(items,negated))
+    }
+}
+
+struct Compiler{
+    prog : Vec<Inst>,
+    slots : usize,
+}
+
+impl Compiler{
+    fn emit(&mut self,inst:Inst)->usize{
+        self.prog.push(inst);
+        self.prog.len()-1
+    }
+
+    fn compile(&mut self,node:&Node){
+        match *node{
+            Node::Char(c) => { self.emit(Inst::Char(c)); },
+            Node::Any => { self.emit(Inst::Any); },
+            Node::Class(ref items,negated) => { self.emit(Inst::Class(items.clone(),negated)); },
+            Node::Start => { self.emit(Inst::Start); },
+            Node::End => { self.emit(Inst::End); },
+            Node::WordBoundary(negated) => { self.emit(Inst::WordBoundary(negated)); },
+            Node::Group(ref inner,None) => self.compile(inner),
+            Node::Group(ref inner,Some(i)) => {
+                self.emit(Inst::Save(2*i));
+                self.compile(inner);
+                self.emit(Inst::Save(2*i+1));
+            },
+            Node::Concat(ref nodes) => {
+                for node in nodes{
+                    self.compile(node);
+                }
+            },
+            Node::Alt(ref alts) => {
+                let mut jumps = vec![];
+                for (i,alt) in alts.iter().enumerate(){
+                    if i == alts.len()-1{
+                        self.compile(alt);
+                    }else{
+                        let split = self.emit(Inst::Split(0,0));
+                        self.compile(alt);
+                        jumps.push(self.emit(Inst::Jmp(0)));
+                        let next = self.prog.len();
+                        self.prog[split] = Inst::Split(split+1,next);
+                    }
+                }
+                let end = self.prog.len();
+                for j in jumps{
+                    self.prog[j] = Inst::Jmp(end);
+                }
+            },
+            Node::Repeat(ref inner,min,max,greedy) => self.compile_repeat(inner,min,max,greedy),
+        }
+    }
+
+    // x{2,4} is compiled as x x (x (x)?)? and x{2,} as x x x*.
+    // An optional iteration which matches nothing fails, so that x* with
+    // an empty x does not loop forever.
+    fn compile_repeat(&mut self,node:&Node,min:usize,max:Option<usize>,greedy:bool){
+        for _ in 0..min{
+            self.compile(node);
+        }
+
+        let split = |body:usize,exit:usize| if greedy{
+            Inst::Split(body,exit)
+        }else{
+            Inst::Split(exit,body)
+        };
+
+        match max{
+            None => {
+                let mark = self.slots;
+                self.slots += 1;
+
+                let start = self.emit(Inst::Split(0,0));
+                self.emit(Inst::Mark(mark));
+                self.compile(node);
+                self.emit(Inst::Progress(mark));
+                self.emit(Inst::Jmp(start));
+                let exit = self.prog.len();
+                self.prog[start] = split(start+1,exit);
+            },
+            Some(max) => {
+                let mut splits = vec![];
+                for _ in min..max{
+                    let mark = self.slots;
+                    self.slots += 1;
+
+                    splits.push(self.emit(Inst::Split(0,0)));
+                    self.emit(Inst::Mark(mark));
+                    self.compile(node);
+                    self.emit(Inst::Progress(mark));
+                }
+                let exit = self.prog.len();
+                for pc in splits{
+                    self.prog[pc] = split(pc+1,exit);
+                }
+            },
+        }
+    }
+}
+
+fn is_boundary(text:&[char],pos:usize)->bool{
+    let before = pos > 0 && is_word_char(text[pos-1]);
+    let after = pos < text.len() && is_word_char(text[pos]);
+    before != after
+}
+
+impl Regex{
+    pub fn new(source:&str)->Result<Regex,String>{
+        let mut parser = Parser{
+            chars : source.chars().collect(),
+            index : 0,
+            groups : 0,
+        };
+
+        let node = match parser.parse_alt(){
+            Ok(v) => v,
+            Err(e) => return Err(e),
+        };
+        if parser.index < parser.chars.len(){
+            return Err(format!("Unmatched ) in regex."));
+        }
+
+        let mut compiler = Compiler{
+            prog : vec![],
+            slots : 2*(parser.groups+1),
+        };
+        compiler.compile(&node);
+        compiler.emit(Inst::Match);
+
+        Ok(Regex{
+            source : source.to_string(),
+            prog : compiler.prog,
+            groups : parser.groups,
+            slots : compiler.slots,
+        })
+    }
+
+    pub fn source(&self)->&str{
+        &self.source
+    }
+
+    pub fn groups(&self)->usize{
+        self.groups
+    }
+
+    // Fails with "Interrupted" when Ctrl-C is pressed.
+    fn match_at(&self,text:&[char],start:usize,must_end:bool)->Result<Option<Captures>,String>{
+        let len = text.len();
+        let mut slots : Vec<Option<usize>> = vec![None;self.slots];
+        let mut stack = vec![Backtrack::Try(0,start)];
+        let mut steps = 0;
+
+        while let Some(b) = stack.pop(){
+            let (mut pc,mut pos) = match b{
+                Backtrack::Try(pc,pos) => (pc,pos),
+                Backtrack::Restore(slot,old) => {
+                    slots[slot] = old;
+                    continue;
+                },
+            };
+
+            loop{
+                steps += 1;
+                if steps % INTERRUPT_INTERVAL == 0 && INTERRUPTED.swap(false,Ordering::SeqCst){
+                    return Err(format!("Interrupted"));
+                }
+
+                let ok = match self.prog[pc]{
+                    Inst::Char(c) => pos < len && text[pos] == c,
+                    Inst::Any => pos < len && text[pos] != '\n',
+                    Inst::Class(ref items,negated) =>
+                        pos < len && items.iter().any(|item| item.matches(text[pos])) != negated,
+                    Inst::Start => { pc += 1; pos == 0 },
+                    Inst::End => { pc += 1; pos == len },
+                    Inst::WordBoundary(negated) => { pc += 1; is_boundary(text,pos) != negated },
+                    Inst::Save(slot) | Inst::Mark(slot) => {
+                        stack.push(Backtrack::Restore(slot,slots[slot]));
+                        slots[slot] = Some(pos);
+                        pc += 1;
+                        continue;
+                    },
+                    Inst::Progress(slot) => { pc += 1; slots[slot] != Some(pos) },
+                    Inst::Split(first,second) => {
+                        stack.push(Backtrack::Try(second,pos));
+                        pc = first;
+                        continue;
+                    },
+                    Inst::Jmp(to) => {
+                        pc = to;
+                        continue;
+                    },
+                    Inst::Match => {
+                        if must_end && pos != len{
+                            false
+                        }else{
+                            let mut caps : Captures = vec![Some((start,pos))];
+                            for i in 1..self.groups+1{
+                                caps.push(match (slots[2*i],slots[2*i+1]){
+                                    (Some(a),Some(b)) => Some((a,b)),
+                                    _ => None,
+                                });
+                            }
+                            return Ok(Some(caps));
+                        }
+                    },
+                };
+
+                if !ok{
+                    break;
+                }
+                // the instructions which consume a character
+                if let Inst::Char(_) | Inst::Any | Inst::Class(_,_) = self.prog[pc]{
+                    pc += 1;
+                    pos += 1;
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    // Finds the first match starting at or after start.
+    pub fn find_at(&self,text:&[char],start:usize)->Result<Option<Captures>,String>{
+        for pos in start..text.len()+1{
+            match self.match_at(text,pos,false){
+                Ok(None) => {},
+                result => return result,
+            }
+        }
+
+        Ok(None)
+    }
+
+    // Matches the whole text.
+    pub fn full_match(&self,text:&[char])->Result<Option<Captures>,String>{
+        self.match_at(text,0,true)
+    }
+
+    pub fn find_all(&self,text:&[char])->Result<Vec<Captures>,String>{
+        let mut result = vec![];
+        let mut pos = 0;
+
+        while pos <= text.len(){
+            let caps = match self.find_at(text,pos){
+                Ok(Some(v)) => v,
+                Ok(None) => break,
+                Err(e) => return Err(e),
+            };
+            let (start,end) = caps[0].unwrap();
+            pos = if end == start{ end+1 }else{ end };
+            result.push(caps);
+        }
+
+        Ok(result)
+    }
+}
+
+#[test]
+fn test_regex_0(){
+    let text : Vec<char> = "2018-07-21 ERROR [main] disk full".chars().collect();
+    let re = Regex::new(r"^(\d{4})-(\d+)-(\d+)\s+([A-Z]+)").unwrap();
+    let caps = re.find_at(&text,0).unwrap().unwrap();
+    assert_eq!(caps[1],Some((0,4)));
+    assert_eq!(caps[4],Some((11,16)));
+
+    let re = Regex::new(r"\[(?:[a-z]|_)+?\]").unwrap();
+    assert_eq!(re.find_at(&text,0).unwrap().unwrap()[0],Some((17,23)));
+
+    let re = Regex::new(r"a|b*").unwrap();
+    assert!(re.full_match(&['b','b']).unwrap().is_some());
+    assert!(re.full_match(&['a','b']).unwrap().is_none());
+
+    assert!(Regex::new("(a").is_err());
+    assert!(Regex::new("[a").is_err());
+    assert!(Regex::new("*a").is_err());
+    assert!(Regex::new("a{100000000}").is_err());
+    assert!(Regex::new("a{2,1001}").is_err());
+    assert!(Regex::new("a{1000}").is_ok());
+}
+
+#[test]
+fn test_regex_1(){
+    // long texts must not overflow the stack
+    let text : Vec<char> = "a".repeat(100000).chars().collect();
+    let re = Regex::new("a*").unwrap();
+    assert_eq!(re.find_at(&text,0).unwrap().unwrap()[0],Some((0,100000)));
+
+    let re = Regex::new("(a|b)*?a$").unwrap();
+    assert_eq!(re.find_at(&text,0).unwrap().unwrap()[0],Some((0,100000)));
+
+    let re = Regex::new("(a)+$").unwrap();
+    assert_eq!(re.full_match(&text).unwrap().unwrap()[1],Some((99999,100000)));
+
+    // an empty iteration does not loop forever
+    let re = Regex::new("(a?)*b").unwrap();
+    assert_eq!(re.find_at(&['a','a','b'],0).unwrap().unwrap()[0],Some((0,3)));
+}
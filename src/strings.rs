@@ -0,0 +1,496 @@
+use types::MalType;
+use regex::{Regex,Captures};
+
+const ORDINALS : [&str;3] = ["first","second","third"];
+
+fn check_arity(fname:&str,xs:&[MalType],min:usize,max:usize)->Result<(),String>{
+    if xs.len() >= min && xs.len() <= max{
+        Ok(())
+    }else if min == max{
+        Err(format!(
+            "The function {} needs exactly {} arguments, we got {}.",fname,min,xs.len()))
+    }else{
+        Err(format!(
+            "The function {} needs {} to {} arguments, we got {}.",fname,min,max,xs.len()))
+    }
+}
+
+fn unwrap_str(fname:&str,xs:&[MalType],n:usize)->Result<String,String>{
+    match xs[n]{
+        MalType::Str(ref s) => Ok(s.clone()),
+        ref x => Err(format!(
+            "The {} argument of {} must be string, we got {}.",
+            ORDINALS[n],fname,x.to_string(true))),
+    }
+}
+
+// accepts a character as a string of length 1
+fn unwrap_str_or_char(fname:&str,xs:&[MalType],n:usize)->Result<String,String>{
+    match xs[n]{
+        MalType::Str(ref s) => Ok(s.clone()),
+        MalType::Char(c) => Ok(c.to_string()),
+        ref x => Err(format!(
+            "The {} argument of {} must be string or character, we got {}.",
+            ORDINALS[n],fname,x.to_string(true))),
+    }
+}
+
+fn unwrap_index(fname:&str,xs:&[MalType],n:usize)->Result<usize,String>{
+    match xs[n]{
+        MalType::Integer(i) if i >= 0 => Ok(i as usize),
+        ref x => Err(format!(
+            "The {} argument of {} must be 0 or positive integer, we got {}.",
+            ORDINALS[n],fname,x.to_string(true))),
+    }
+}
+
+// Converts an index of chars into an index of bytes.
+fn byte_index(s:&str,char_index:usize)->Option<usize>{
+    if char_index == s.chars().count(){
+        Some(s.len())
+    }else{
+        s.char_indices().nth(char_index).map(|(i,_)| i)
+    }
+}
+
+pub fn mal_subs(xs:Vec<MalType>)->Result<MalType,String>{
+    // (subs "hello" 1 3) -> "el"
+    if let Err(e) = check_arity("subs",&xs,2,3){
+        return Err(e);
+    }
+    let s = match unwrap_str("subs",&xs,0){
+        Ok(v) => v, Err(e) => return Err(e),
+    };
+    let len = s.chars().count();
+    let start = match unwrap_index("subs",&xs,1){
+        Ok(v) => v, Err(e) => return Err(e),
+    };
+    let end = if xs.len() == 3{
+        match unwrap_index("subs",&xs,2){
+            Ok(v) => v, Err(e) => return Err(e),
+        }
+    }else{
+        len
+    };
+
+    if start > end || end > len{
+        return Err(format!(
+            "The range {}..{} is out of bounds for string of length {}.",start,end,len));
+    }
+
+    Ok(MalType::Str(s.chars().skip(start).take(end-start).collect()))
+}
+
+// a character whose case maps to several characters, such as \u00df, is kept as it is
+fn change_case<I:Iterator<Item=char>>(c:char,mut cs:I)->char{
+    match (cs.next(),cs.next()){
+        (Some(d),None) => d,
+        _ => c,
+    }
+}
+
+pub fn mal_upper_case(xs:Vec<MalType>)->Result<MalType,String>{
+    if let Err(e) = check_arity("upper-case",&xs,1,1){
+        return Err(e);
+    }
+    match xs[0]{
+        MalType::Char(c) => Ok(MalType::Char(change_case(c,c.to_uppercase()))),
+        _ => match unwrap_str("upper-case",&xs,0){
+            Ok(s) => Ok(MalType::Str(s.to_uppercase())),
+            Err(e) => Err(e),
+        },
+    }
+}
+
+pub fn mal_lower_case(xs:Vec<MalType>)->Result<MalType,String>{
+    if let Err(e) = check_arity("lower-case",&xs,1,1){
+        return Err(e);
+    }
+    match xs[0]{
+        MalType::Char(c) => Ok(MalType::Char(change_case(c,c.to_lowercase()))),
+        _ => match unwrap_str("lower-case",&xs,0){
+            Ok(s) => Ok(MalType::Str(s.to_lowercase())),
+            Err(e) => Err(e),
+        },
+    }
+}
+
+pub fn mal_trim(xs:Vec<MalType>)->Result<MalType,String>{
+    if let Err(e) = check_arity("trim",&xs,1,1){
+        return Err(e);
+    }
+    match unwrap_str("trim",&xs,0){
+        Ok(s) => Ok(MalType::Str(s.trim().to_string())),
+        Err(e) => Err(e),
+    }
+}
+
+pub fn mal_split(xs:Vec<MalType>)->Result<MalType,String>{
+    // (split "a,b,c" ",") -> ("a" "b" "c")
+    if let Err(e) = check_arity("split",&xs,2,2){
+        return Err(e);
+    }
+    let s = match unwrap_str("split",&xs,0){
+        Ok(v) => v, Err(e) => return Err(e),
+    };
+    let sep = match unwrap_str_or_char("split",&xs,1){
+        Ok(v) => v, Err(e) => return Err(e),
+    };
+
+    let ys = if sep.len() == 0{
+        // (split "abc" "") -> (\a \b \c), the same as (seq "abc")
+        s.chars()
+            .map(MalType::Char)
+            .collect()
+    }else{
+        s.split(sep.as_str())
+            .map(|x| MalType::Str(x.to_string()))
+            .collect()
+    };
+
+    Ok(MalType::List(ys))
+}
+
+pub fn mal_join(xs:Vec<MalType>)->Result<MalType,String>{
+    // (join ", " [1 2 3]) -> "1, 2, 3"
+    if let Err(e) = check_arity("join",&xs,1,2){
+        return Err(e);
+    }
+    let (sep,ys) = if xs.len() == 2{
+        match unwrap_str_or_char("join",&xs,0){
+            Ok(v) => (v,xs[1].clone()),
+            Err(e) => return Err(e),
+        }
+    }else{
+        (String::new(),xs[0].clone())
+    };
+
+    let ys = match ys{
+        MalType::Nil => vec![],
+        ys => match ys.unwrap_sequence(){
+            Some(v) => v,
+            None => return Err(format!(
+                "The last argument of join must be sequence, we got {}.",
+                ys.to_string(true))),
+        },
+    };
+
+    let ys : Vec<String> = ys
+        .iter()
+        .map(|y| y.to_string(false))
+        .collect();
+
+    Ok(MalType::Str(ys.join(&sep)))
+}
+
+pub fn mal_replace(xs:Vec<MalType>)->Result<MalType,String>{
+    // (replace "hello" "l" "L") -> "heLLo"
+    if let Err(e) = check_arity("replace",&xs,3,3){
+        return Err(e);
+    }
+    let s = match unwrap_str("replace",&xs,0){
+        Ok(v) => v, Err(e) => return Err(e),
+    };
+    let from = match unwrap_str_or_char("replace",&xs,1){
+        Ok(v) => v, Err(e) => return Err(e),
+    };
+    let to = match unwrap_str_or_char("replace",&xs,2){
+        Ok(v) => v, Err(e) => return Err(e),
+    };
+
+    if from.len() == 0{
+        return Err(format!("The second argument of replace must not be empty."));
+    }
+
+    Ok(MalType::Str(s.replace(from.as_str(),to.as_str())))
+}
+
+pub fn mal_starts_with(xs:Vec<MalType>)->Result<MalType,String>{
+    if let Err(e) = check_arity("starts-with?",&xs,2,2){
+        return Err(e);
+    }
+    let s = match unwrap_str("starts-with?",&xs,0){
+        Ok(v) => v, Err(e) => return Err(e),
+    };
+    let prefix = match unwrap_str_or_char("starts-with?",&xs,1){
+        Ok(v) => v, Err(e) => return Err(e),
+    };
+
+    Ok(MalType::Bool(s.starts_with(prefix.as_str())))
+}
+
+pub fn mal_ends_with(xs:Vec<MalType>)->Result<MalType,String>{
+    if let Err(e) = check_arity("ends-with?",&xs,2,2){
+        return Err(e);
+    }
+    let s = match unwrap_str("ends-with?",&xs,0){
+        Ok(v) => v, Err(e) => return Err(e),
+    };
+    let suffix = match unwrap_str_or_char("ends-with?",&xs,1){
+        Ok(v) => v, Err(e) => return Err(e),
+    };
+
+    Ok(MalType::Bool(s.ends_with(suffix.as_str())))
+}
+
+pub fn mal_index_of(xs:Vec<MalType>)->Result<MalType,String>{
+    // (index-of "héllo" "l") -> 2, index is counted in characters
+    if let Err(e) = check_arity("index-of",&xs,2,3){
+        return Err(e);
+    }
+    let s = match unwrap_str("index-of",&xs,0){
+        Ok(v) => v, Err(e) => return Err(e),
+    };
+    let x = match unwrap_str_or_char("index-of",&xs,1){
+        Ok(v) => v, Err(e) => return Err(e),
+    };
+    let from = if xs.len() == 3{
+        match unwrap_index("index-of",&xs,2){
+            Ok(v) => v, Err(e) => return Err(e),
+        }
+    }else{
+        0
+    };
+
+    let from = match byte_index(&s,from){
+        Some(v) => v,
+        None => return Ok(MalType::Nil),
+    };
+
+    match s[from..].find(x.as_str()){
+        Some(i) => Ok(MalType::Integer(s[..from+i].chars().count() as i64)),
+        None => Ok(MalType::Nil),
+    }
+}
+
+pub fn mal_char_to_int(xs:Vec<MalType>)->Result<MalType,String>{
+    if let Err(e) = check_arity("char->int",&xs,1,1){
+        return Err(e);
+    }
+    match xs[0]{
+        MalType::Char(c) => Ok(MalType::Integer(c as i64)),
+        ref x => Err(format!(
+            "The argument of char->int must be character, we got {}.",
+            x.to_string(true))),
+    }
+}
+
+pub fn mal_int_to_char(xs:Vec<MalType>)->Result<MalType,String>{
+    if let Err(e) = check_arity("int->char",&xs,1,1){
+        return Err(e);
+    }
+    match xs[0]{
+        MalType::Integer(n) if (0..=0x10FFFF).contains(&n) => {
+            match ::std::char::from_u32(n as u32){
+                Some(c) => Ok(MalType::Char(c)),
+                None => Err(format!("{} is not a valid code point.",n)),
+            }
+        },
+        ref x => Err(format!(
+            "The argument of int->char must be valid code point, we got {}.",
+            x.to_string(true))),
+    }
+}
+
+fn unwrap_regex(fname:&str,xs:&[MalType],n:usize)->Result<Regex,String>{
+    match xs[n]{
+        MalType::Regex(ref re) => Ok(re.clone()),
+        MalType::Str(ref s) => Regex::new(s),
+        ref x => Err(format!(
+            "The {} argument of {} must be regex, we got {}.",
+            ORDINALS[n],fname,x.to_string(true))),
+    }
+}
+
+// "abc" for a regex without groups, ["abc" "a" nil] for a regex with groups
+fn captures_to_mal(re:&Regex,text:&[char],caps:&Captures)->MalType{
+    let group = |cap:&Option<(usize,usize)>| match *cap{
+        Some((start,end)) => MalType::Str(text[start..end].iter().collect()),
+        None => MalType::Nil,
+    };
+
+    if re.groups() == 0{
+        group(&caps[0])
+    }else{
+        MalType::Vector(caps.iter().map(group).collect())
+    }
+}
+
+pub fn mal_re_pattern(xs:Vec<MalType>)->Result<MalType,String>{
+    if let Err(e) = check_arity("re-pattern",&xs,1,1){
+        return Err(e);
+    }
+    match unwrap_regex("re-pattern",&xs,0){
+        Ok(re) => Ok(MalType::Regex(re)),
+        Err(e) => Err(e),
+    }
+}
+
+pub fn mal_re_find(xs:Vec<MalType>)->Result<MalType,String>{
+    // (re-find #"\d+" "ab12cd") -> "12"
+    if let Err(e) = check_arity("re-find",&xs,2,2){
+        return Err(e);
+    }
+    let re = match unwrap_regex("re-find",&xs,0){
+        Ok(v) => v, Err(e) => return Err(e),
+    };
+    let text : Vec<char> = match unwrap_str("re-find",&xs,1){
+        Ok(v) => v.chars().collect(), Err(e) => return Err(e),
+    };
+
+    match re.find_at(&text,0){
+        Ok(Some(caps)) => Ok(captures_to_mal(&re,&text,&caps)),
+        Ok(None) => Ok(MalType::Nil),
+        Err(e) => Err(e),
+    }
+}
+
+pub fn mal_re_matches(xs:Vec<MalType>)->Result<MalType,String>{
+    if let Err(e) = check_arity("re-matches",&xs,2,2){
+        return Err(e);
+    }
+    let re = match unwrap_regex("re-matches",&xs,0){
+        Ok(v) => v, Err(e) => return Err(e),
+    };
+    let text : Vec<char> = match unwrap_str("re-matches",&xs,1){
+        Ok(v) => v.chars().collect(), Err(e) => return Err(e),
+    };
+
+    match re.full_match(&text){
+        Ok(Some(caps)) => Ok(captures_to_mal(&re,&text,&caps)),
+        Ok(None) => Ok(MalType::Nil),
+        Err(e) => Err(e),
+    }
+}
+
+pub fn mal_re_seq(xs:Vec<MalType>)->Result<MalType,String>{
+    if let Err(e) = check_arity("re-seq",&xs,2,2){
+        return Err(e);
+    }
+    let re = match unwrap_regex("re-seq",&xs,0){
+        Ok(v) => v, Err(e) => return Err(e),
+    };
+    let text : Vec<char> = match unwrap_str("re-seq",&xs,1){
+        Ok(v) => v.chars().collect(), Err(e) => return Err(e),
+    };
+
+    let all = match re.find_all(&text){
+        Ok(v) => v, Err(e) => return Err(e),
+    };
+    let ys : Vec<MalType> = all
+        .iter()
+        .map(|caps| captures_to_mal(&re,&text,caps))
+        .collect();
+
+    if ys.len() == 0{
+        Ok(MalType::Nil)
+    }else{
+        Ok(MalType::List(ys))
+    }
+}
+
+// the match, its groups and the text -> the replacement
+pub type Replacer<'a> = dyn FnMut(MalType,&Captures,&[char])->Result<String,String> + 'a;
+
+// Replaces every match of the second argument in the first argument.
+// replace is called with each match and returns the replacement.
+pub fn regex_replace(xs:&[MalType],replace:&mut Replacer)
+    ->Result<MalType,String>{
+    if let Err(e) = check_arity("re-replace",xs,3,3){
+        return Err(e);
+    }
+    let text : Vec<char> = match unwrap_str("re-replace",xs,0){
+        Ok(v) => v.chars().collect(), Err(e) => return Err(e),
+    };
+    let re = match unwrap_regex("re-replace",xs,1){
+        Ok(v) => v, Err(e) => return Err(e),
+    };
+
+    let mut result = String::new();
+    let mut last = 0;
+
+    let all = match re.find_all(&text){
+        Ok(v) => v, Err(e) => return Err(e),
+    };
+    for caps in all{
+        let (start,end) = caps[0].unwrap();
+        result.extend(text[last..start].iter());
+        match replace(captures_to_mal(&re,&text,&caps),&caps,&text){
+            Ok(s) => result.push_str(&s),
+            Err(e) => return Err(e),
+        }
+        last = end;
+    }
+    result.extend(text[last..].iter());
+
+    Ok(MalType::Str(result))
+}
+
+pub fn mal_re_replace(xs:Vec<MalType>)->Result<MalType,String>{
+    // (re-replace "a1b22" #"(\d)+" "<$1>") -> "a<1>b<2>"
+    if let Err(e) = check_arity("re-replace",&xs,3,3){
+        return Err(e);
+    }
+    let template : Vec<char> = match unwrap_str("re-replace",&xs,2){
+        Ok(v) => v.chars().collect(), Err(e) => return Err(e),
+    };
+
+    regex_replace(&xs,&mut |_,caps,text|{
+        let mut s = String::new();
+        let mut i = 0;
+
+        while i < template.len(){
+            let c = template[i];
+            i += 1;
+
+            if c != '$'{
+                s.push(c);
+            }else if template.get(i) == Some(&'$'){
+                s.push('$');
+                i += 1;
+            }else{
+                let start = i;
+                while i < template.len() && template[i].is_ascii_digit(){
+                    i += 1;
+                }
+                let digits : String = template[start..i].iter().collect();
+                let n = match digits.parse::<usize>(){
+                    Ok(v) if v < caps.len() => v,
+                    _ => return Err(format!(
+                        "Invalid group reference ${} in replacement.",digits)),
+                };
+                if let Some((start,end)) = caps[n]{
+                    s.extend(text[start..end].iter());
+                }
+            }
+        }
+
+        Ok(s)
+    })
+}
+
+#[test]
+fn test_strings_0(){
+    let s = MalType::Str("héllo wörld".to_string());
+
+    assert_eq!(
+        mal_subs(vec![s.clone(),MalType::Integer(1),MalType::Integer(4)]),
+        Ok(MalType::Str("éll".to_string())));
+    assert_eq!(
+        mal_index_of(vec![s.clone(),MalType::Char('w')]),
+        Ok(MalType::Integer(6)));
+    assert_eq!(
+        mal_upper_case(vec![s.clone()]),
+        Ok(MalType::Str("HÉLLO WÖRLD".to_string())));
+    assert_eq!(mal_upper_case(vec![MalType::Char('é')]),Ok(MalType::Char('É')));
+    assert_eq!(mal_upper_case(vec![MalType::Char('ß')]),Ok(MalType::Char('ß')));
+    assert_eq!(mal_lower_case(vec![MalType::Char('Ö')]),Ok(MalType::Char('ö')));
+    assert_eq!(
+        mal_split(vec![MalType::Str("ab".to_string()),MalType::Str(String::new())]),
+        Ok(MalType::List(vec![MalType::Char('a'),MalType::Char('b')])));
+    assert_eq!(
+        mal_split(vec![s,MalType::Str(" ".to_string())]),
+        Ok(MalType::List(vec![
+            MalType::Str("héllo".to_string()),
+            MalType::Str("wörld".to_string())])));
+}
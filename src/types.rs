@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use regex::Regex;
 
 #[derive(PartialEq, Debug, Clone)]
 pub enum TokenKind{
@@ -6,6 +7,9 @@ pub enum TokenKind{
     Integer(i64), 
     Symbol(String),     // [],(),{},`,',@,~,~@,^
     Str(String),
+    Char(char),
+    Regex(String),
+    Comment(String),    // only from Lexer::with_comments
 }
 
 #[derive(PartialEq, Debug, Clone)]
@@ -20,18 +24,30 @@ pub enum MalType{
     Identifier(String),
     Integer(i64),
     Str(String),
+    Char(char),
+    Regex(Regex),
     Bool(bool),
     Vector(Vec<MalType>),
     List(Vec<MalType>),
-    // varnames, body, & rest, local_env, is_macro
-    Function(Vec<String>,Box<MalType>,bool,HashMap<String,MalType>,bool), 
+    // varnames, body, & rest, local_env, is_macro, docstring
+    Function(Vec<String>,Box<MalType>,bool,HashMap<String,MalType>,bool,Option<String>), 
     BuiltInFunction(BuiltInFunction), 
     Keyword(String),
     Dict(HashMap<String,MalType>),
     Atom(usize),
+    LazySeq(usize),
+    // index of Interpreter::files
+    File(usize),
     Nil,
 }
 
+#[derive(PartialEq, Debug, Clone)]
+pub enum LazySeqState{
+    Thunk(MalType),         // function with no arguments, not yet called
+    Cons(MalType,MalType),  // first element, rest of sequence
+    Empty,
+}
+
 #[derive(PartialEq, Debug, Clone)]
 pub enum BuiltInFunction{
     Add,
@@ -40,6 +56,7 @@ pub enum BuiltInFunction{
     Div,
     HashMap,
     Exit,
+    AtExit,
     Def,
     Let,
     Fn,
@@ -85,60 +102,234 @@ pub enum BuiltInFunction{
     ReadLine,
     Seq,
     TimeMs,
+    LazySeq,
+    Cons,
+    Count,
+    Subs,
+    UpperCase,
+    LowerCase,
+    Trim,
+    Split,
+    Join,
+    Replace,
+    StartsWith,
+    EndsWith,
+    IndexOf,
+    CharToInt,
+    IntToChar,
+    RePattern,
+    ReFind,
+    ReMatches,
+    ReSeq,
+    ReReplace,
+    Doc,
+    Apropos,
+    Pprint,
+    Ns,
+    Require,
+    DefPrivate,
+    Gensym,
+    MacroExpand,
+    MacroExpand1,
+    MacroExpandAll,
+    Vec,
+    Finally,
+    ExInfo,
+    ExData,
+    ExMessage,
+    Open,
+    Write,
+    Close,
+    WithOpen,
+    FileReadLine,
 }
 
-pub const BUILD_IN_FUNCTION_NAMES : [(&str,BuiltInFunction);51] = [
-    ("+",BuiltInFunction::Add),
-    ("-",BuiltInFunction::Sub),
-    ("*",BuiltInFunction::Mul),
-    ("/",BuiltInFunction::Div),
-    ("hash-map",BuiltInFunction::HashMap),
-    ("exit",BuiltInFunction::Exit),
-    ("def!",BuiltInFunction::Def),
-    ("let*",BuiltInFunction::Let),
-    ("fn*",BuiltInFunction::Fn),
-    ("if",BuiltInFunction::If),
-    ("load-file",BuiltInFunction::LoadFile),
-    ("<",BuiltInFunction::Lt),
-    ("=",BuiltInFunction::Eq),
-    ("quote",BuiltInFunction::Quote),
-    ("nth",BuiltInFunction::Nth),
-    ("rest",BuiltInFunction::Rest),
-    ("type-str",BuiltInFunction::TypeStr),
-    ("insert",BuiltInFunction::Insert),
-    ("eval",BuiltInFunction::Eval),
-    ("err",BuiltInFunction::Err),
-    ("print-string",BuiltInFunction::PrintString),
-    ("pr-str",BuiltInFunction::PrStr),
-    ("str",BuiltInFunction::Str),
-    ("apply",BuiltInFunction::Apply),
-    ("do",BuiltInFunction::Do),
-    ("slurp",BuiltInFunction::Slurp),
-    ("read-string",BuiltInFunction::ReadString),
-    ("atom",BuiltInFunction::Atom),
-    ("atom-at",BuiltInFunction::AtomAt),
-    ("deref",BuiltInFunction::Deref),
-    ("reset!",BuiltInFunction::Reset),
-    ("unquote",BuiltInFunction::UnQuote),
-    ("splice-unquote",BuiltInFunction::SpliceUnQuote),
-    ("quasiquote",BuiltInFunction::QuasiQuote),
-    ("concat",BuiltInFunction::ConCat),
-    ("defmacro!",BuiltInFunction::DefMacro),
-    ("throw",BuiltInFunction::Throw),
-    ("try*",BuiltInFunction::Try),
-    ("catch*",BuiltInFunction::Catch),
-    ("symbol",BuiltInFunction::Symbol),
-    ("keyword",BuiltInFunction::Keyword),
-    ("vector",BuiltInFunction::Vector),
-    ("assoc",BuiltInFunction::Assoc),
-    ("get",BuiltInFunction::Get),
-    ("contains?",BuiltInFunction::Contains),
-    ("keys",BuiltInFunction::Keys),
-    ("vals",BuiltInFunction::Vals),
-    ("dissoc",BuiltInFunction::Dissoc),
-    ("readline",BuiltInFunction::ReadLine),
-    ("seq",BuiltInFunction::Seq),
-    ("time-ms",BuiltInFunction::TimeMs),
+// name, function, docstring
+pub const BUILD_IN_FUNCTION_NAMES : [(&str,BuiltInFunction,&str);92] = [
+    ("+",BuiltInFunction::Add,
+        "Returns the sum of the integers."),
+    ("-",BuiltInFunction::Sub,
+        "Subtracts the rest of the integers from the first one."),
+    ("*",BuiltInFunction::Mul,
+        "Returns the product of the integers."),
+    ("/",BuiltInFunction::Div,
+        "Divides the first integer by the rest of them, rounding toward zero."),
+    ("hash-map",BuiltInFunction::HashMap,
+        "Returns a new dict of the given keys and values."),
+    ("exit",BuiltInFunction::Exit,
+        "Exits with the status code n, or 0 if it is omitted. Runs the at-exit functions."),
+    ("at-exit",BuiltInFunction::AtExit,
+        "Registers a function of no arguments to be called at exit."),
+    ("def!",BuiltInFunction::Def,
+        "(def! name doc? value) Binds the value to the name in the current scope."),
+    ("let*",BuiltInFunction::Let,
+        "(let* [name value ...] body) Evaluates the body with local bindings."),
+    ("fn*",BuiltInFunction::Fn,
+        "(fn* doc? [params] body) Returns a function. & before the last parameter takes the rest of the arguments."),
+    ("if",BuiltInFunction::If,
+        "(if test then else?) Evaluates then if test is neither false nor nil, otherwise else."),
+    ("load-file",BuiltInFunction::LoadFile,
+        "(load-file path) Evaluates the file. A relative path is looked for next to the loading file, then in *load-path*."),
+    ("<",BuiltInFunction::Lt,
+        "Returns true if the first integer is less than the second one."),
+    ("=",BuiltInFunction::Eq,
+        "Returns true if the two values are equal."),
+    ("quote",BuiltInFunction::Quote,
+        "(quote form) Returns the form without evaluating it."),
+    ("nth",BuiltInFunction::Nth,
+        "Returns the element of the sequence at the index."),
+    ("rest",BuiltInFunction::Rest,
+        "Returns the sequence without its first element."),
+    ("type-str",BuiltInFunction::TypeStr,
+        "Returns the name of the type of the value."),
+    ("insert",BuiltInFunction::Insert,
+        "Returns the sequence with the value inserted at the index."),
+    ("eval",BuiltInFunction::Eval,
+        "Evaluates the form."),
+    ("err",BuiltInFunction::Err,
+        "Raises an error with the message."),
+    ("print-string",BuiltInFunction::PrintString,
+        "Prints the strings without quotes."),
+    ("pr-str",BuiltInFunction::PrStr,
+        "Returns the readable representation of the values joined with spaces."),
+    ("str",BuiltInFunction::Str,
+        "Returns the concatenation of the values printed without quotes."),
+    ("apply",BuiltInFunction::Apply,
+        "Calls the function with the arguments, the last of which is a sequence spread as arguments."),
+    ("do",BuiltInFunction::Do,
+        "(do forms...) Evaluates the forms in order and returns the last value."),
+    ("slurp",BuiltInFunction::Slurp,
+        "Returns the content of the file as a string."),
+    ("open",BuiltInFunction::Open,
+        "(open path mode?) Opens the file and returns its handle. The mode is :read (default), :write or :append."),
+    ("read-line",BuiltInFunction::FileReadLine,
+        "Returns the next line of the file without the line break, or nil at the end."),
+    ("write",BuiltInFunction::Write,
+        "(write file x ...) Writes the arguments to the file like str."),
+    ("close",BuiltInFunction::Close,
+        "Closes the file. Closing a closed file does nothing."),
+    ("with-open",BuiltInFunction::WithOpen,
+        "(with-open [name file ...] body ...) Evaluates the body and closes the files in reverse order even if it fails."),
+    ("read-string",BuiltInFunction::ReadString,
+        "Reads the string as code and returns the form."),
+    ("atom",BuiltInFunction::Atom,
+        "Returns a new atom holding the value."),
+    ("atom-at",BuiltInFunction::AtomAt,
+        "Returns the atom with the index."),
+    ("deref",BuiltInFunction::Deref,
+        "Returns the value in the atom. @a is (deref a)."),
+    ("reset!",BuiltInFunction::Reset,
+        "Sets the value of the atom and returns it."),
+    ("unquote",BuiltInFunction::UnQuote,
+        "~form evaluates the form inside quasiquote."),
+    ("splice-unquote",BuiltInFunction::SpliceUnQuote,
+        "~@form evaluates the form and splices the sequence inside quasiquote."),
+    ("quasiquote",BuiltInFunction::QuasiQuote,
+        "`form quotes the form except ~ and ~@ parts."),
+    ("concat",BuiltInFunction::ConCat,
+        "Returns the concatenation of the sequences."),
+    ("defmacro!",BuiltInFunction::DefMacro,
+        "(defmacro! name doc? fn) Binds the function as a macro."),
+    ("throw",BuiltInFunction::Throw,
+        "Throws the value, which catch* receives."),
+    ("try*",BuiltInFunction::Try,
+        "(try* expr (catch* e handler) ... (finally* cleanup ...)) Evaluates the handler of the first catch* which selects the error, and the cleanup in any case."),
+    ("catch*",BuiltInFunction::Catch,
+        "(catch* selector? e handler) The handler clause of try*. The selector is :error, :thrown, :ex-info, :default or a predicate of the error."),
+    ("finally*",BuiltInFunction::Finally,
+        "The last clause of try*, which is evaluated whether the expression fails or not."),
+    ("ex-info",BuiltInFunction::ExInfo,
+        "(ex-info message data?) Returns an error value with the message and the data to throw."),
+    ("ex-data",BuiltInFunction::ExData,
+        "Returns the data of the ex-info, or nil."),
+    ("ex-message",BuiltInFunction::ExMessage,
+        "Returns the message of the ex-info or the error string, or nil."),
+    ("symbol",BuiltInFunction::Symbol,
+        "Returns the symbol of the name."),
+    ("keyword",BuiltInFunction::Keyword,
+        "Returns the keyword of the name."),
+    ("vector",BuiltInFunction::Vector,
+        "Returns a vector of the arguments."),
+    ("vec",BuiltInFunction::Vec,
+        "Returns a vector of the elements of the sequence."),
+    ("assoc",BuiltInFunction::Assoc,
+        "Returns the dict with the keys and values added."),
+    ("get",BuiltInFunction::Get,
+        "Returns the value of the key in the dict, or nil."),
+    ("contains?",BuiltInFunction::Contains,
+        "Returns true if the dict has the key."),
+    ("keys",BuiltInFunction::Keys,
+        "Returns the list of the keys of the dict."),
+    ("vals",BuiltInFunction::Vals,
+        "Returns the list of the values of the dict."),
+    ("dissoc",BuiltInFunction::Dissoc,
+        "Returns the dict without the keys."),
+    ("readline",BuiltInFunction::ReadLine,
+        "Prints the prompt and returns a line read from the standard input."),
+    ("seq",BuiltInFunction::Seq,
+        "Returns the sequence as a list, or nil if it is empty. Strings become lists of characters."),
+    ("time-ms",BuiltInFunction::TimeMs,
+        "Returns the current time in milliseconds."),
+    ("lazy-seq",BuiltInFunction::LazySeq,
+        "(lazy-seq body) Returns a lazy sequence which evaluates the body when it is needed."),
+    ("cons",BuiltInFunction::Cons,
+        "Returns the sequence with the value added at the front."),
+    ("count",BuiltInFunction::Count,
+        "Returns the number of the elements of the sequence, dict or string."),
+    ("subs",BuiltInFunction::Subs,
+        "Returns the substring from start to end, or to the end of the string."),
+    ("upper-case",BuiltInFunction::UpperCase,
+        "Returns the string or character in upper case."),
+    ("lower-case",BuiltInFunction::LowerCase,
+        "Returns the string or character in lower case."),
+    ("trim",BuiltInFunction::Trim,
+        "Returns the string without leading and trailing whitespace."),
+    ("split",BuiltInFunction::Split,
+        "Splits the string by the separator string or character, or into characters by \"\"."),
+    ("join",BuiltInFunction::Join,
+        "Joins the elements of the sequence with the separator, or without it."),
+    ("replace",BuiltInFunction::Replace,
+        "Replaces every match of the string or character with the replacement."),
+    ("starts-with?",BuiltInFunction::StartsWith,
+        "Returns true if the string starts with the prefix."),
+    ("ends-with?",BuiltInFunction::EndsWith,
+        "Returns true if the string ends with the suffix."),
+    ("index-of",BuiltInFunction::IndexOf,
+        "Returns the index of the first occurrence of the string or character, searching from the optional index, or nil."),
+    ("char->int",BuiltInFunction::CharToInt,
+        "Returns the code point of the character."),
+    ("int->char",BuiltInFunction::IntToChar,
+        "Returns the character of the code point."),
+    ("re-pattern",BuiltInFunction::RePattern,
+        "Compiles the string into a regex."),
+    ("re-find",BuiltInFunction::ReFind,
+        "Returns the first match of the regex in the string, or nil."),
+    ("re-matches",BuiltInFunction::ReMatches,
+        "Returns the match if the regex matches the whole string, or nil."),
+    ("re-seq",BuiltInFunction::ReSeq,
+        "Returns the list of all the matches of the regex in the string."),
+    ("re-replace",BuiltInFunction::ReReplace,
+        "Replaces every match of the regex with the template ($1 for a group) or the result of the function."),
+    ("doc",BuiltInFunction::Doc,
+        "Returns the docstring of the function, or nil."),
+    ("pprint",BuiltInFunction::Pprint,
+        "Prints the value laid out to fit the width, or *print-width* if it is omitted."),
+    ("apropos",BuiltInFunction::Apropos,
+        "Returns the sorted list of the defined symbols whose names contain the string or match the regex."),
+    ("ns",BuiltInFunction::Ns,
+        "(ns name) Creates the namespace if needed and makes it current. def! in it does not clobber other namespaces."),
+    ("require",BuiltInFunction::Require,
+        "(require 'my.lib) or (require '[my.lib :as l]) Loads my/lib.mal from *load-path* unless it is loaded, so that l/name refers to its definitions."),
+    ("def-!",BuiltInFunction::DefPrivate,
+        "(def-! name doc? value) Like def!, but the name cannot be used from other namespaces."),
+    ("gensym",BuiltInFunction::Gensym,
+        "Returns a new symbol G__n, or prefix followed by n. Inside quasiquote, x# does the same."),
+    ("macroexpand",BuiltInFunction::MacroExpand,
+        "Expands the macro call repeatedly until the form is not a macro call, without evaluating it."),
+    ("macroexpand-1",BuiltInFunction::MacroExpand1,
+        "Expands the macro call once without evaluating it. Other forms are returned as they are."),
+    ("macroexpand-all",BuiltInFunction::MacroExpandAll,
+        "Expands the macro calls in the form and all its subforms except quoted ones, without evaluating it."),
 ];
 
 impl MalType{
@@ -152,15 +343,48 @@ impl MalType{
             },
             MalType::Str(s) => {
                 if print_readably{
-                    format!("\"{}\"",
-                        s.replace("\n","\\n")
-                        .replace("\r","\\r")
-                        .replace("\t","\\t")
-                        .replace("\"","\\\""))
+                    let mut escaped = String::new();
+                    for c in s.chars(){
+                        match c{
+                            '\\' => escaped.push_str("\\\\"),
+                            '\"' => escaped.push_str("\\\""),
+                            '\n' => escaped.push_str("\\n"),
+                            '\r' => escaped.push_str("\\r"),
+                            '\t' => escaped.push_str("\\t"),
+                            '\0' => escaped.push_str("\\0"),
+                            c if c.is_control() => 
+                                escaped.push_str(&format!("\\u{{{:x}}}",c as u32)),
+                            c => escaped.push(c),
+                        }
+                    }
+                    format!("\"{}\"",escaped)
                 }else{
                     s.clone()
                 }
             },
+            MalType::Char(c) => {
+                if print_readably{
+                    match c{
+                        '\n' => "\\newline".to_string(),
+                        ' ' => "\\space".to_string(),
+                        '\t' => "\\tab".to_string(),
+                        '\r' => "\\return".to_string(),
+                        '\u{8}' => "\\backspace".to_string(),
+                        '\u{c}' => "\\formfeed".to_string(),
+                        c if c.is_control() => format!("\\u{:04x}",*c as u32),
+                        c => format!("\\{}",c),
+                    }
+                }else{
+                    c.to_string()
+                }
+            },
+            MalType::Regex(re) => {
+                if print_readably{
+                    format!("#\"{}\"",re.source().replace("\"","\\\""))
+                }else{
+                    re.source().to_string()
+                }
+            },
             MalType::Bool(b) => {
                 format!("{}",b)
             },
@@ -182,19 +406,26 @@ impl MalType{
 
                 format!("({})",joined)
             },
-            MalType::Function(args,ast,flag,_,_)=>{
+            MalType::Function(args,ast,flag,_,_,doc)=>{
                 let mut args = args.clone();
                 if *flag{
                     let idx = args.len()-1;
                     args.insert(idx, "&".to_string());
                 }
 
-                format!("(fn* [{}] {})",
+                // the docstring is printed where (fn* "doc" [args] body) takes it
+                let doc = match doc{
+                    Some(s) => format!("{} ",MalType::Str(s.clone()).to_string(print_readably)),
+                    None => String::new(),
+                };
+
+                format!("(fn* {}[{}] {})",
+                    doc,
                     args.join(" "),
                     ast.to_string(print_readably))
             },
             MalType::BuiltInFunction(t) => {
-                for (fname,ftype) in BUILD_IN_FUNCTION_NAMES.iter(){
+                for (fname,ftype,_) in BUILD_IN_FUNCTION_NAMES.iter(){
                     if ftype == t{
                         return fname.to_string();
                     }
@@ -230,13 +461,19 @@ impl MalType{
             MalType::Atom(i) => {
                 format!("(atom-at {})",i)
             },
+            MalType::LazySeq(i) => {
+                format!("#<lazy-seq {}>",i)
+            },
+            MalType::File(i) => {
+                format!("#<file {}>",i)
+            },
         }
     }
 }
 
 impl MalType{
     pub fn unwrap_function(&self)->Option<(Vec<String>,MalType,bool,HashMap<String,MalType>,bool)>{
-        if let MalType::Function(a,b,c,d,e) = self{
+        if let MalType::Function(a,b,c,d,e,_) = self{
             // let b = b;
             // let b = (*b).clone();
             // let b = *b;
@@ -307,6 +544,10 @@ impl MalType{
         }
     }
     
+    pub fn is_string(&self)->bool{
+        matches!(self,MalType::Str(_))
+    }
+
     pub fn is_vector(&self)->bool{
         if let MalType::Vector(_)=self{
             true
@@ -318,6 +559,10 @@ impl MalType{
     pub fn is_sequence(&self)->bool{
         self.is_list() || self.is_vector()
     }
+
+    pub fn is_lazy_seq(&self)->bool{
+        matches!(self,MalType::LazySeq(_))
+    }
 }
 
 impl MalType{
@@ -342,6 +587,15 @@ impl MalType{
 
                 ys
             },
+            MalType::Dict(hm) => {
+                let mut ys = Vec::new();
+                
+                for x in hm.values(){
+                    ys.append(&mut (x.get_all_identifier()));
+                }
+
+                ys
+            },
             _ => Vec::new(),
         }
     }